@@ -0,0 +1,106 @@
+//! Blocking on an atomic's address, instead of on a thread handle
+//! like `thread::park` / `unpark` do. Whoever changes the atomic
+//! only needs the atomic to wake up everybody waiting on it.
+//!
+//! These are thin wrappers around the Linux futex syscall.
+
+use std::{io, ptr, sync::atomic::AtomicU32, time::Duration};
+
+/// Sleeps until woken up by `wake_one` or `wake_all`, but only if
+/// the atomic still holds `expected`. That check is done by the
+/// kernel, so a wake-up that happens in between can't be missed.
+///
+/// Might also return spuriously, so always check the atomic again.
+pub fn wait(a: &AtomicU32, expected: u32) {
+    wait_timeout(a, expected, None);
+}
+
+/// Same as `wait`, but gives up after `timeout`.
+///
+/// Returns false if the timeout elapsed, true otherwise
+/// (which includes spurious wake-ups).
+pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+    let timespec = timeout.map(|d| libc::timespec {
+        tv_sec: d.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: d.subsec_nanos() as _,
+    });
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            a as *const AtomicU32,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            timespec
+                .as_ref()
+                .map_or(ptr::null(), |t| t as *const libc::timespec),
+        )
+    };
+    !(r < 0 && io::Error::last_os_error().raw_os_error() == Some(libc::ETIMEDOUT))
+}
+
+/// Wakes up one of the threads waiting on `a`, if any.
+pub fn wake_one(a: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            a as *const AtomicU32,
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            1i32,
+        );
+    }
+}
+
+/// Wakes up all threads waiting on `a`.
+pub fn wake_all(a: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            a as *const AtomicU32,
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            i32::MAX,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering::*},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{wait, wait_timeout, wake_all, wake_one};
+
+    #[test]
+    fn test_wait_and_wake() {
+        let a = AtomicU32::new(0);
+
+        // Doesn't sleep if the value is already different
+        wait(&a, 1);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                a.store(1, Release);
+                wake_one(&a);
+            });
+            while a.load(Acquire) == 0 {
+                wait(&a, 0);
+            }
+        });
+
+        // Nobody's waiting, nothing happens
+        wake_all(&a);
+        assert_eq!(a.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let a = AtomicU32::new(0);
+        let start = Instant::now();
+        assert!(!wait_timeout(&a, 0, Some(Duration::from_millis(10))));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(wait_timeout(&a, 1, Some(Duration::from_secs(10))));
+    }
+}
@@ -1,7 +1,6 @@
-use std::{
-    collections::VecDeque,
-    sync::{Condvar, Mutex},
-};
+use std::collections::VecDeque;
+
+use crate::locks::{condvar::Condvar, mutex::Mutex};
 
 /// Basic implementation of a channel
 /// that can send and receive messages across
@@ -22,17 +21,17 @@ impl<T> BasicChannel<T> {
     }
 
     pub fn send(&self, message: T) {
-        self.queue.lock().unwrap().push_back(message);
+        self.queue.lock().push_back(message);
         self.item_ready.notify_one();
     }
 
     pub fn receive(&self) -> T {
-        let mut guard = self.queue.lock().unwrap();
+        let mut guard = self.queue.lock();
         loop {
             if let Some(message) = guard.pop_front() {
                 return message;
             }
-            guard = self.item_ready.wait(guard).unwrap();
+            guard = self.item_ready.wait(guard);
         }
     }
 }
@@ -64,6 +64,12 @@ impl<T> OneShotChannel<T> {
     }
 }
 
+impl<T> Default for OneShotChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 ///
 /// We now have a fully safe interface, though there is still
 ///  one problem left. The last remaining issue occurs
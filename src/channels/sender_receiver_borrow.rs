@@ -1,21 +1,18 @@
-use std::{
-    cell::UnsafeCell,
-    marker::PhantomData,
-    mem::MaybeUninit,
-    sync::atomic::AtomicBool,
-    thread::{self, Thread},
-};
+use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicU32};
+
+use crate::atomic_wait::{wait, wake_one};
 
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
-    ready: AtomicBool,
+    /// 1 once the message is there. Not a bool,
+    /// so the receiver can wait on it.
+    ready: AtomicU32,
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
 
 pub struct Sender<'a, T> {
     channel: &'a Channel<T>,
-    receiving_thread: Thread,
 }
 
 impl<T> Sender<'_, T> {
@@ -23,18 +20,13 @@ impl<T> Sender<'_, T> {
         unsafe { (*self.channel.message.get()).write(message) };
         self.channel
             .ready
-            .store(true, std::sync::atomic::Ordering::Release);
-        self.receiving_thread.unpark();
+            .store(1, std::sync::atomic::Ordering::Release);
+        wake_one(&self.channel.ready);
     }
 }
 
 pub struct Receiver<'a, T> {
     channel: &'a Channel<T>,
-    // use the special PhantomData marker type to add this restriction to our struct
-    // by not allowing it to be sent between threads anymore.
-    // A PhantomData<*const ()> does the job, since a raw pointer, such as *const (),
-    // does not implement Send:
-    _no_send: PhantomData<*const ()>,
 }
 
 impl<T> Receiver<'_, T> {
@@ -42,15 +34,20 @@ impl<T> Receiver<'_, T> {
         self.channel
             .ready
             .load(std::sync::atomic::Ordering::Relaxed)
+            == 1
     }
 
     pub fn receive(self) -> T {
-        if !self
+        // Waiting on the address of the flag, so unlike with
+        // park / unpark, the sender doesn't need to know which
+        // thread is receiving, and the receiver can be sent anywhere.
+        while self
             .channel
             .ready
-            .swap(false, std::sync::atomic::Ordering::Acquire)
+            .swap(0, std::sync::atomic::Ordering::Acquire)
+            == 0
         {
-            thread::park();
+            wait(&self.channel.ready, 0);
         }
         unsafe { (*self.channel.message.get()).assume_init_read() }
     }
@@ -60,28 +57,25 @@ impl<T> Channel<T> {
     pub const fn new() -> Self {
         Self {
             message: UnsafeCell::new(MaybeUninit::uninit()),
-            ready: AtomicBool::new(false),
+            ready: AtomicU32::new(0),
         }
     }
 
     pub fn split<'a>(&'a mut self) -> (Sender<'a, T>, Receiver<'a, T>) {
         *self = Self::new();
-        (
-            Sender {
-                channel: self,
-                receiving_thread: thread::current(),
-            },
-            Receiver {
-                channel: self,
-                _no_send: PhantomData,
-            },
-        )
+        (Sender { channel: self }, Receiver { channel: self })
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
+        if *self.ready.get_mut() == 1 {
             unsafe { self.message.get_mut().assume_init_drop() };
         }
     }
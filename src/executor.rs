@@ -0,0 +1,326 @@
+//! Just enough of an async runtime to run futures: `block_on` for
+//! a single future, and a small thread pool for spawning tasks.
+//!
+//! The pool's run queue is one of our own channels, and every
+//! task sends its result back through a one-shot channel.
+
+use std::{
+    future::Future,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicBool, Ordering::*},
+        Arc, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, JoinHandle as ThreadJoinHandle, Thread},
+};
+
+use crate::{
+    channels::{
+        basic_channel::BasicChannel,
+        sender_receiver::{channel, Receiver},
+    },
+    locks::mutex::Mutex,
+    sync::event::ManualResetEvent,
+};
+
+/// Wakes up the thread that's blocked in `block_on`.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to completion on the current thread,
+/// parking the thread whenever the future is pending.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            // Unparking before we get here just makes this return
+            // right away, so a wake-up can't get lost. Waking up
+            // for no reason only costs an extra poll.
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// None tells a worker to stop.
+type RunQueue = BasicChannel<Option<Arc<Task>>>;
+
+struct Task {
+    /// None once the task has completed.
+    future: Mutex<Option<BoxFuture>>,
+    /// Weak, so tasks that are never woken up again
+    /// don't keep the queue alive after the executor is gone.
+    queue: Weak<RunQueue>,
+    /// Whether the task is in the run queue already,
+    /// so waking it up many times only queues it once.
+    scheduled: AtomicBool,
+}
+
+impl Task {
+    fn schedule(self: &Arc<Self>) {
+        if !self.scheduled.swap(true, AcqRel) {
+            if let Some(queue) = self.queue.upgrade() {
+                queue.send(Some(self.clone()));
+            }
+        }
+    }
+
+    fn run(self: Arc<Self>) {
+        // Cleared before polling, so waking up the task
+        // while it's being polled schedules it again.
+        self.scheduled.store(false, Release);
+        let mut future = self.future.lock();
+        let Some(fut) = future.as_mut() else {
+            return;
+        };
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        // A panicking task is dropped, which its JoinHandle
+        // notices, but it doesn't take the worker down with it.
+        match panic::catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(&mut cx))) {
+            Ok(Poll::Pending) => {}
+            Ok(Poll::Ready(())) | Err(_) => *future = None,
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.schedule();
+    }
+}
+
+/// A fixed number of worker threads running spawned tasks.
+///
+/// Dropping the executor lets the workers finish running what's
+/// already in the queue, then stops them. The futures of tasks
+/// that haven't completed by then are dropped, so joining
+/// them panics instead of waiting forever.
+pub struct Executor {
+    queue: Arc<RunQueue>,
+    workers: Vec<ThreadJoinHandle<()>>,
+    /// Every task that might still be alive. A task's waker can end up
+    /// in its own future, so we can't count on tasks being dropped
+    /// by themselves once the executor is gone.
+    tasks: Mutex<Vec<Weak<Task>>>,
+}
+
+impl Executor {
+    pub fn new(num_threads: usize) -> Self {
+        assert!(num_threads > 0, "executor needs at least one thread");
+        let queue = Arc::new(RunQueue::new());
+        let workers = (0..num_threads)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    while let Some(task) = queue.receive() {
+                        task.run();
+                    }
+                })
+            })
+            .collect();
+        Self {
+            queue,
+            workers,
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let finished = Arc::new(ManualResetEvent::new(false));
+        let on_exit = SetOnDrop(finished.clone());
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(async move {
+                let _on_exit = on_exit;
+                sender.send(fut.await);
+            }))),
+            queue: Arc::downgrade(&self.queue),
+            scheduled: AtomicBool::new(false),
+        });
+
+        let mut tasks = self.tasks.lock();
+        // Forgetting about completed tasks only every now and then,
+        // so spawning stays cheap even with lots of tasks
+        if tasks.len() == tasks.capacity() {
+            tasks.retain(|task| task.strong_count() > 0);
+        }
+        tasks.push(Arc::downgrade(&task));
+        drop(tasks);
+
+        task.schedule();
+        JoinHandle { receiver, finished }
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        for _ in &self.workers {
+            self.queue.send(None);
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        let tasks = mem::take(&mut *self.tasks.lock());
+        for task in tasks {
+            if let Some(task) = task.upgrade() {
+                // Dropped outside of the lock, in case
+                // dropping it wakes up this very task
+                let future = task.future.lock().take();
+                drop(future);
+            }
+        }
+    }
+}
+
+/// Sets the event when the task's future is dropped,
+/// whether it completed or not.
+struct SetOnDrop(Arc<ManualResetEvent>);
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        self.0.set();
+    }
+}
+
+/// Gets the result of a spawned task.
+pub struct JoinHandle<T> {
+    receiver: Receiver<T>,
+    finished: Arc<ManualResetEvent>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_set()
+    }
+
+    /// Blocks until the task has completed and returns its output.
+    ///
+    /// Panics if the task panicked, or if it was dropped
+    /// before completing because the executor was dropped.
+    pub fn join(self) -> T {
+        self.finished.wait();
+        if !self.receiver.is_ready() {
+            panic!("task didn't complete");
+        }
+        self.receiver.receive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::poll_fn,
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicBool, Ordering::*},
+            Arc,
+        },
+        task::Poll,
+        thread,
+        time::Duration,
+    };
+
+    use super::{block_on, Executor};
+    use crate::locks::async_mutex::AsyncMutex;
+
+    /// Pending until another thread sets the flag and wakes us up.
+    async fn woken_by_other_thread() -> u32 {
+        let done = Arc::new(AtomicBool::new(false));
+        let mut started = false;
+        poll_fn(|cx| {
+            if done.load(Acquire) {
+                return Poll::Ready(42);
+            }
+            if !started {
+                started = true;
+                let (done, waker) = (done.clone(), cx.waker().clone());
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    done.store(true, Release);
+                    waker.wake();
+                });
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    #[test]
+    fn test_block_on() {
+        assert_eq!(block_on(async { 1 + 2 }), 3);
+        assert_eq!(block_on(woken_by_other_thread()), 42);
+    }
+
+    #[test]
+    fn test_executor() {
+        let executor = Executor::new(2);
+        let counter = Arc::new(AsyncMutex::new(0));
+
+        let handles: Vec<_> = (0..10)
+            .map(|i| {
+                let counter = counter.clone();
+                executor.spawn(async move {
+                    let n = woken_by_other_thread().await;
+                    *counter.lock().await += 1;
+                    n + i
+                })
+            })
+            .collect();
+
+        let results: Vec<_> = handles.into_iter().map(|h| h.join()).collect();
+        assert_eq!(results, (42..52).collect::<Vec<_>>());
+        assert_eq!(*counter.try_lock().unwrap(), 10);
+    }
+
+    #[test]
+    fn test_executor_task_panics() {
+        let executor = Executor::new(1);
+
+        let handle = executor.spawn(async { panic!("oops") });
+        assert!(catch_unwind(AssertUnwindSafe(|| handle.join())).is_err());
+
+        // The worker survived
+        assert_eq!(executor.spawn(async { 5 }).join(), 5);
+    }
+
+    #[test]
+    fn test_executor_drop_cancels_pending_tasks() {
+        let executor = Executor::new(1);
+
+        // Never completes, and its own future holds on to its waker
+        let handle = executor.spawn(async {
+            let mutex = AsyncMutex::new(());
+            let _guard = mutex.try_lock().unwrap();
+            mutex.lock().await;
+        });
+        // The workers get to poll it once before they stop
+        drop(executor);
+        assert!(handle.is_finished());
+        assert!(catch_unwind(AssertUnwindSafe(|| handle.join())).is_err());
+    }
+}
@@ -1,3 +1,7 @@
+pub mod atomic_wait;
 pub mod channels;
+pub mod executor;
 pub mod locks;
+pub mod parking_lot;
 pub mod reference_counting;
+pub mod sync;
@@ -0,0 +1,324 @@
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use super::mutex::Mutex;
+
+/// A mutex for async code: waiting for it suspends the
+/// task instead of blocking the thread, and the guard can
+/// be held across an `.await`.
+///
+/// Waiting tasks get the lock in the order they started waiting.
+/// Unlocking hands the lock straight to the first one in line,
+/// so a newly arriving task can't take it in between.
+pub struct AsyncMutex<T> {
+    /// Only ever locked for a short moment,
+    /// never while somebody holds the AsyncMutex.
+    state: Mutex<State>,
+    value: UnsafeCell<T>,
+}
+
+struct State {
+    locked: bool,
+    /// The waiter the lock was handed to, which
+    /// hasn't been polled to pick it up yet.
+    handed_to: Option<u64>,
+    queue: VecDeque<Waiter>,
+    next_id: u64,
+}
+
+struct Waiter {
+    id: u64,
+    waker: Waker,
+}
+
+unsafe impl<T> Sync for AsyncMutex<T> where T: Send {}
+
+impl<T> AsyncMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: Mutex::new(State {
+                locked: false,
+                handed_to: None,
+                queue: VecDeque::new(),
+                next_id: 0,
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockFuture<'_, T> {
+        LockFuture {
+            mutex: self,
+            id: None,
+            done: false,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        let mut state = self.state.lock();
+        if state.locked {
+            return None;
+        }
+        state.locked = true;
+        Some(AsyncMutexGuard { mutex: self })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Hands the lock to the next waiter in line, or unlocks it.
+    fn release(&self) {
+        let mut state = self.state.lock();
+        let waker = match state.queue.pop_front() {
+            Some(waiter) => {
+                state.handed_to = Some(waiter.id);
+                Some(waiter.waker)
+            }
+            None => {
+                state.locked = false;
+                None
+            }
+        };
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Returned by `AsyncMutex::lock`.
+///
+/// Dropping it while waiting gives up its place in line. If the
+/// lock was already handed to it, it goes to the next one in line,
+/// so no wakeup gets lost.
+#[must_use = "futures do nothing unless polled"]
+pub struct LockFuture<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+    /// Our place in line, once we've had to wait.
+    id: Option<u64>,
+    done: bool,
+}
+
+impl<'a, T> Future for LockFuture<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        assert!(!self.done, "LockFuture polled after completion");
+        let mutex = self.mutex;
+        let mut state = mutex.state.lock();
+
+        match self.id {
+            None if !state.locked => state.locked = true,
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.queue.push_back(Waiter {
+                    id,
+                    waker: cx.waker().clone(),
+                });
+                self.id = Some(id);
+                return Poll::Pending;
+            }
+            Some(id) if state.handed_to == Some(id) => state.handed_to = None,
+            Some(id) => {
+                // We might be polled by a different task than last time
+                if let Some(waiter) = state.queue.iter_mut().find(|w| w.id == id) {
+                    waiter.waker.clone_from(cx.waker());
+                }
+                return Poll::Pending;
+            }
+        }
+
+        drop(state);
+        self.done = true;
+        Poll::Ready(AsyncMutexGuard { mutex })
+    }
+}
+
+impl<T> Drop for LockFuture<'_, T> {
+    fn drop(&mut self) {
+        let Some(id) = self.id.filter(|_| !self.done) else {
+            return;
+        };
+        let mut state = self.mutex.state.lock();
+        if state.handed_to == Some(id) {
+            state.handed_to = None;
+            drop(state);
+            self.mutex.release();
+        } else {
+            state.queue.retain(|w| w.id != id);
+        }
+    }
+}
+
+pub struct AsyncMutexGuard<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+unsafe impl<T> Sync for AsyncMutexGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the mutex
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the mutex
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.release();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        sync::{
+            atomic::{AtomicUsize, Ordering::*},
+            Arc,
+        },
+        task::{Context, Poll, Wake, Waker},
+        thread,
+    };
+
+    use super::AsyncMutex;
+    use crate::executor::block_on;
+
+    /// Counts how often it was woken up.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Gives other tasks a chance to run, by returning Pending once.
+    async fn yield_now() {
+        let mut yielded = false;
+        std::future::poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    #[test]
+    fn test_async_mutex_counter() {
+        let mutex = AsyncMutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    block_on(async {
+                        for _ in 0..100 {
+                            let mut guard = mutex.lock().await;
+                            let value = *guard;
+                            // Holding the guard across an await
+                            yield_now().await;
+                            *guard = value + 1;
+                        }
+                    })
+                });
+            }
+        });
+        assert_eq!(mutex.into_inner(), 400);
+    }
+
+    #[test]
+    fn test_async_mutex_fifo() {
+        let mutex = AsyncMutex::new(Vec::new());
+        let guard = mutex.try_lock().unwrap();
+
+        let wakers: Vec<_> = (0..3).map(|_| Arc::new(CountingWaker::default())).collect();
+        let mut futures: Vec<_> = (0..3).map(|_| Box::pin(mutex.lock())).collect();
+        for (fut, waker) in futures.iter_mut().zip(&wakers) {
+            let waker = Waker::from(waker.clone());
+            assert!(fut
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_pending());
+        }
+
+        // The lock was handed to the first one, not up for grabs
+        drop(guard);
+        assert!(mutex.try_lock().is_none());
+
+        for i in 0..3 {
+            assert_eq!(wakers[i].0.load(Relaxed), 1);
+            let waker = Waker::from(wakers[i].clone());
+            let Poll::Ready(mut guard) = futures[i].as_mut().poll(&mut Context::from_waker(&waker))
+            else {
+                panic!("lock wasn't handed to the next waiter");
+            };
+            guard.push(i);
+            // The others are still waiting
+            assert!(wakers[i + 1..].iter().all(|w| w.0.load(Relaxed) == 0));
+        }
+
+        assert_eq!(*mutex.try_lock().unwrap(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_async_mutex_cancel() {
+        let mutex = AsyncMutex::new(());
+        let guard = mutex.try_lock().unwrap();
+
+        let wakers: Vec<_> = (0..3).map(|_| Arc::new(CountingWaker::default())).collect();
+        let mut futures: Vec<_> = (0..3).map(|_| Some(Box::pin(mutex.lock()))).collect();
+        for (fut, waker) in futures.iter_mut().zip(&wakers) {
+            let waker = Waker::from(waker.clone());
+            let fut = fut.as_mut().unwrap();
+            assert!(fut
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_pending());
+        }
+
+        // Cancelling a waiter just takes it out of line
+        futures[1] = None;
+
+        // Cancelling after the lock was handed over passes it on
+        drop(guard);
+        assert_eq!(wakers[0].0.load(Relaxed), 1);
+        futures[0] = None;
+        assert_eq!(wakers[1].0.load(Relaxed), 0);
+        assert_eq!(wakers[2].0.load(Relaxed), 1);
+
+        let waker = Waker::from(wakers[2].clone());
+        let fut = futures[2].as_mut().unwrap();
+        assert!(fut
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready());
+        assert!(mutex.try_lock().is_some());
+    }
+}
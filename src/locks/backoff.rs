@@ -0,0 +1,92 @@
+use std::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, Ordering::Relaxed},
+    thread,
+};
+
+/// What a SpinLock does after failing to take the lock.
+///
+/// A fresh instance (through `Default`) is created for every
+/// `lock()` call, so implementations can keep per-acquisition
+/// state like the number of failed attempts so far.
+pub trait Backoff: Default {
+    /// Called after every failed compare-and-exchange.
+    /// `locked` is the lock's flag, in case the strategy
+    /// wants to watch it before the next attempt.
+    fn snooze(&mut self, locked: &AtomicBool);
+}
+
+/// A single `spin_loop()` hint per failed attempt.
+/// This is what SpinLock has always done.
+#[derive(Debug, Default)]
+pub struct SpinLoop;
+
+impl Backoff for SpinLoop {
+    fn snooze(&mut self, _locked: &AtomicBool) {
+        spin_loop();
+    }
+}
+
+/// Doubles the number of `spin_loop()` hints after every
+/// failed attempt (up to 2^MAX_STEP), which takes
+/// pressure off the lock's cache line under contention.
+#[derive(Debug, Default)]
+pub struct Exponential {
+    step: u32,
+}
+
+impl Exponential {
+    const MAX_STEP: u32 = 10;
+}
+
+impl Backoff for Exponential {
+    fn snooze(&mut self, _locked: &AtomicBool) {
+        for _ in 0..1 << self.step {
+            spin_loop();
+        }
+        if self.step < Self::MAX_STEP {
+            self.step += 1;
+        }
+    }
+}
+
+/// Backs off exponentially for a few rounds and then starts
+/// yielding to the OS scheduler, so a lock holder that got
+/// preempted gets a chance to run and release the lock.
+#[derive(Debug, Default)]
+pub struct SpinThenYield {
+    step: u32,
+}
+
+impl SpinThenYield {
+    const SPIN_LIMIT: u32 = 6;
+}
+
+impl Backoff for SpinThenYield {
+    fn snooze(&mut self, _locked: &AtomicBool) {
+        if self.step < Self::SPIN_LIMIT {
+            for _ in 0..1 << self.step {
+                spin_loop();
+            }
+            self.step += 1;
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Test-and-test-and-set: waits with plain loads until
+/// the lock looks free before retrying the compare-and-exchange.
+///
+/// Loads keep the cache line shared between the waiting cores,
+/// while every failed compare-and-exchange takes it exclusively.
+#[derive(Debug, Default)]
+pub struct TestAndTestAndSet;
+
+impl Backoff for TestAndTestAndSet {
+    fn snooze(&mut self, locked: &AtomicBool) {
+        while locked.load(Relaxed) {
+            spin_loop();
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    ops::DerefMut,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering::*},
+    time::{Duration, Instant},
+};
+
+use crate::atomic_wait::{wait, wait_timeout, wake_all, wake_one};
+
+/// Lock guards a Condvar can wait on.
+///
+/// Waiting means giving up the lock, sleeping until notified
+/// and taking the lock back before the guard is handed
+/// back to the caller.
+pub trait Relock: Sized {
+    /// Releases the lock, runs `f` and locks it again.
+    fn relock_after(self, f: impl FnOnce()) -> Self;
+}
+
+/// A condition variable that works with the crate's
+/// own lock guards (Mutex and SpinLock).
+///
+/// Every notification bumps `counter`, so a waiter that
+/// read the counter before unlocking can't miss a notify
+/// that happens between unlocking and going to sleep.
+pub struct Condvar {
+    counter: AtomicU32,
+    num_waiters: AtomicUsize,
+}
+
+/// Tells whether `Condvar::wait_timeout` returned
+/// because the timeout elapsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            num_waiters: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        // No waiters, no syscall
+        if self.num_waiters.load(Relaxed) > 0 {
+            self.counter.fetch_add(1, Relaxed);
+            wake_one(&self.counter);
+        }
+    }
+
+    pub fn notify_all(&self) {
+        if self.num_waiters.load(Relaxed) > 0 {
+            self.counter.fetch_add(1, Relaxed);
+            wake_all(&self.counter);
+        }
+    }
+
+    /// Might wake up spuriously, so always check
+    /// the condition again (or use `wait_while`).
+    pub fn wait<G: Relock>(&self, guard: G) -> G {
+        // Registering as a waiter happens while still holding the lock,
+        // so a notifying thread that took the lock after us will see it.
+        self.num_waiters.fetch_add(1, Relaxed);
+        let counter_value = self.counter.load(Relaxed);
+
+        let guard = guard.relock_after(|| wait(&self.counter, counter_value));
+
+        self.num_waiters.fetch_sub(1, Relaxed);
+        guard
+    }
+
+    /// Keeps waiting for as long as `condition` returns true.
+    pub fn wait_while<G, T>(&self, mut guard: G, mut condition: impl FnMut(&mut T) -> bool) -> G
+    where
+        G: Relock + DerefMut<Target = T>,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn wait_timeout<G: Relock>(&self, guard: G, timeout: Duration) -> (G, WaitTimeoutResult) {
+        let deadline = Instant::now() + timeout;
+        self.num_waiters.fetch_add(1, Relaxed);
+        let counter_value = self.counter.load(Relaxed);
+
+        let mut timed_out = false;
+        let guard = guard.relock_after(|| loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                timed_out = true;
+                return;
+            }
+            // A spurious wake-up with an unchanged counter goes back to sleep,
+            // so a notification is the only way out before the deadline.
+            if wait_timeout(&self.counter, counter_value, Some(remaining))
+                && self.counter.load(Relaxed) != counter_value
+            {
+                return;
+            }
+        });
+
+        self.num_waiters.fetch_sub(1, Relaxed);
+        (guard, WaitTimeoutResult(timed_out))
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::Condvar;
+    use crate::locks::{mutex::Mutex, spin_lock::SpinLock};
+
+    #[test]
+    fn test_condvar() {
+        let mutex = Mutex::new(0);
+        let condvar = Condvar::new();
+
+        let mut wakeups = 0;
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                *mutex.lock() = 123;
+                condvar.notify_one();
+            });
+
+            let mut m = mutex.lock();
+            while *m < 100 {
+                m = condvar.wait(m);
+                wakeups += 1;
+            }
+
+            assert_eq!(*m, 123);
+        });
+
+        // Check that the main thread actually did wait (not busy-loop),
+        // while still allowing for a few spurious wake ups.
+        assert!(wakeups < 10);
+    }
+
+    #[test]
+    fn test_condvar_spin_lock() {
+        let lock = SpinLock::new(false);
+        let condvar = Condvar::new();
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                *lock.lock() = true;
+                condvar.notify_all();
+            });
+
+            let ready = condvar.wait_while(lock.lock(), |ready| !*ready);
+            assert!(*ready);
+        });
+    }
+
+    #[test]
+    fn test_condvar_timeout() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+
+        let (_guard, result) = condvar.wait_timeout(mutex.lock(), Duration::from_millis(10));
+        assert!(result.timed_out());
+    }
+}
@@ -0,0 +1,339 @@
+//! Lock-order deadlock detection, enabled with the
+//! `deadlock-detection` cargo feature.
+//!
+//! Every time a thread takes a lock while already holding others,
+//! an edge "held -> new" is added to a global lock-order graph,
+//! together with the backtraces of both acquisitions. Taking a lock
+//! that would close a cycle in that graph means two threads could
+//! end up waiting on each other forever, so we panic right away
+//! (before spinning or sleeping), showing both acquisition orders.
+//!
+//! Without the feature, `LockId` is zero-sized and all of this compiles
+//! down to nothing.
+
+#[cfg(feature = "deadlock-detection")]
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+/// Identifies a lock in the lock-order graph.
+///
+/// Ids are handed out lazily on first use, so locks can still be
+/// created in const contexts, and unlike addresses they're never reused.
+pub(crate) struct LockId {
+    #[cfg(feature = "deadlock-detection")]
+    id: AtomicUsize,
+}
+
+impl LockId {
+    pub(crate) const fn new() -> Self {
+        Self {
+            #[cfg(feature = "deadlock-detection")]
+            id: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called right before blocking on the lock.
+    /// Panics if taking it could deadlock.
+    pub(crate) fn acquire(&self) {
+        #[cfg(feature = "deadlock-detection")]
+        detector::check(self.get());
+    }
+
+    /// Called once the lock is ours, after `acquire` or a successful
+    /// try_lock (which can't deadlock by itself but still needs to be
+    /// tracked). Waiting threads don't count as holding the lock yet.
+    pub(crate) fn acquired(&self) {
+        #[cfg(feature = "deadlock-detection")]
+        detector::acquired(self.get());
+    }
+
+    pub(crate) fn release(&self) {
+        #[cfg(feature = "deadlock-detection")]
+        detector::release(self.get());
+    }
+
+    #[cfg(feature = "deadlock-detection")]
+    fn get(&self) -> usize {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+        let id = self.id.load(Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let new_id = NEXT_ID.fetch_add(1, Relaxed);
+        match self.id.compare_exchange(0, new_id, Relaxed, Relaxed) {
+            Ok(_) => new_id,
+            // Another thread beat us to it, use theirs
+            Err(id) => id,
+        }
+    }
+}
+
+#[cfg(feature = "deadlock-detection")]
+mod detector {
+    use std::{
+        backtrace::Backtrace,
+        collections::{HashMap, HashSet},
+        fmt::Write,
+        sync::{
+            atomic::{AtomicU64, Ordering::Relaxed},
+            Arc, Mutex,
+        },
+    };
+
+    /// Where the two locks of an edge were taken,
+    /// the first time that order was seen.
+    struct Edge {
+        held: Arc<Backtrace>,
+        acquired: Arc<Backtrace>,
+    }
+
+    #[derive(Default)]
+    struct State {
+        graph: HashMap<usize, HashMap<usize, Edge>>,
+        /// Locks held by every thread, in acquisition order.
+        held: HashMap<u64, Vec<(usize, Arc<Backtrace>)>>,
+        /// The thread holding each lock. Guards can be dropped on
+        /// another thread, and the lock has to leave the list
+        /// of the thread that took it, not the one dropping it.
+        owners: HashMap<usize, u64>,
+    }
+
+    /// Using std's Mutex here on purpose, so the detector
+    /// never ends up checking its own lock.
+    static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+    static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+    thread_local! {
+        static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Relaxed);
+    }
+
+    fn current_thread_id() -> u64 {
+        // Locks taken while the thread is being torn down
+        // all end up with the same (otherwise unused) id
+        THREAD_ID.try_with(|id| *id).unwrap_or(0)
+    }
+
+    pub(super) fn check(id: usize) {
+        let backtrace = Arc::new(Backtrace::force_capture());
+        let thread = current_thread_id();
+
+        let report = {
+            let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+            let state = state.get_or_insert_with(State::default);
+            let held = state.held.get(&thread).cloned().unwrap_or_default();
+
+            // Looking for cycles first, so a lock order that's
+            // rejected doesn't leave any edges behind.
+            let report = if let Some((_, first)) = held.iter().find(|(h, _)| *h == id) {
+                Some(format!(
+                    "deadlock: lock #{id} is already held by this thread\n\
+                     first acquired at:\n{first}\n\
+                     acquired again at:\n{backtrace}"
+                ))
+            } else {
+                held.iter().find_map(|(h, held_backtrace)| {
+                    if state
+                        .graph
+                        .get(h)
+                        .is_some_and(|edges| edges.contains_key(&id))
+                    {
+                        return None;
+                    }
+                    find_path(&state.graph, id, *h)
+                        .map(|path| cycle_report(&state.graph, &path, held_backtrace, &backtrace))
+                })
+            };
+
+            if report.is_none() {
+                for (h, held_backtrace) in &held {
+                    state
+                        .graph
+                        .entry(*h)
+                        .or_default()
+                        .entry(id)
+                        .or_insert_with(|| Edge {
+                            held: held_backtrace.clone(),
+                            acquired: backtrace.clone(),
+                        });
+                }
+            }
+            report
+        };
+        // The state lock is released by now, so the panic doesn't poison it
+        if let Some(report) = report {
+            panic!("{report}");
+        }
+    }
+
+    pub(super) fn acquired(id: usize) {
+        let backtrace = Arc::new(Backtrace::force_capture());
+        let thread = current_thread_id();
+
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        let state = state.get_or_insert_with(State::default);
+        state.held.entry(thread).or_default().push((id, backtrace));
+        state.owners.insert(id, thread);
+    }
+
+    pub(super) fn release(id: usize) {
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+        let Some(thread) = state.owners.remove(&id) else {
+            return;
+        };
+        // Locks don't have to be released in the order they were taken
+        if let Some(held) = state.held.get_mut(&thread) {
+            if let Some(i) = held.iter().rposition(|(h, _)| *h == id) {
+                held.remove(i);
+            }
+            if held.is_empty() {
+                state.held.remove(&thread);
+            }
+        }
+    }
+
+    /// Depth-first search for a path of "taken while holding" edges.
+    fn find_path(
+        graph: &HashMap<usize, HashMap<usize, Edge>>,
+        from: usize,
+        to: usize,
+    ) -> Option<Vec<usize>> {
+        let mut visited = HashSet::new();
+        let mut stack = vec![vec![from]];
+        while let Some(path) = stack.pop() {
+            let last = *path.last().unwrap();
+            if last == to {
+                return Some(path);
+            }
+            if !visited.insert(last) {
+                continue;
+            }
+            for next in graph.get(&last).into_iter().flat_map(|edges| edges.keys()) {
+                let mut path = path.clone();
+                path.push(*next);
+                stack.push(path);
+            }
+        }
+        None
+    }
+
+    fn cycle_report(
+        graph: &HashMap<usize, HashMap<usize, Edge>>,
+        path: &[usize],
+        held_backtrace: &Backtrace,
+        backtrace: &Backtrace,
+    ) -> String {
+        let (first, last) = (path[0], path[path.len() - 1]);
+        let mut report = format!(
+            "deadlock: lock order inversion between lock #{last} and lock #{first}\n\n\
+             this thread holds lock #{last}, acquired at:\n{held_backtrace}\n\
+             and now wants lock #{first}, at:\n{backtrace}\n"
+        );
+        for pair in path.windows(2) {
+            let edge = &graph[&pair[0]][&pair[1]];
+            let _ = write!(
+                report,
+                "\nearlier, lock #{} was held, acquired at:\n{}\n\
+                 while taking lock #{}, at:\n{}\n",
+                pair[0], edge.held, pair[1], edge.acquired
+            );
+        }
+        report
+    }
+}
+
+#[cfg(all(test, feature = "deadlock-detection"))]
+mod test {
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        thread,
+    };
+
+    use crate::locks::{
+        mutex::Mutex,
+        spin_lock::{Guard, SpinLock},
+    };
+
+    #[test]
+    #[should_panic(expected = "lock order inversion")]
+    fn test_lock_order_inversion() {
+        let a = SpinLock::new(());
+        let b = Mutex::new(());
+
+        // a -> b on one thread
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _a = a.lock();
+                let _b = b.lock();
+            });
+        });
+
+        // b -> a on another one, which could deadlock
+        // if both ran at the same time
+        let _b = b.lock();
+        let _a = a.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "already held by this thread")]
+    fn test_relock_same_thread() {
+        let a = SpinLock::new(());
+        let _first = a.lock();
+        let _second = a.lock();
+    }
+
+    #[test]
+    fn test_consistent_order() {
+        let a = SpinLock::new(());
+        let b = SpinLock::new(());
+        for _ in 0..2 {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        // Taking them one at a time is fine in any order
+        drop(b.lock());
+        drop(a.lock());
+    }
+
+    #[test]
+    fn test_release_on_other_thread() {
+        let a = Mutex::new(());
+        let b = SpinLock::new((0, 0));
+
+        let guard = a.lock();
+        thread::scope(|s| {
+            s.spawn(move || drop(guard));
+        });
+        drop(a.lock());
+
+        let mapped = Guard::map(b.lock(), |(x, _)| x);
+        thread::scope(|s| {
+            s.spawn(move || drop(mapped));
+        });
+        drop(b.lock());
+    }
+
+    #[test]
+    fn test_rejected_order_leaves_no_edges() {
+        let a = SpinLock::new(());
+        let b = SpinLock::new(());
+        let c = SpinLock::new(());
+
+        // a -> b
+        drop((a.lock(), b.lock()));
+
+        // b -> c -> a closes the cycle at a, so c -> a
+        // is never recorded, and neither is b -> a
+        let _b = b.lock();
+        let _c = c.lock();
+        let inversion = catch_unwind(AssertUnwindSafe(|| drop(a.lock())));
+        assert!(inversion.is_err());
+        drop((_c, _b));
+
+        // a -> c is fine, it would only clash with the rejected c -> a
+        drop((a.lock(), c.lock()));
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering::*},
+};
+
+use crate::atomic_wait::{wait, wake_one};
+
+use super::{
+    condvar::Relock,
+    deadlock::LockId,
+    stats::{HoldTimer, Recorder, WaitTimer},
+};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2;
+
+/// Upper limit for the spin budget, no matter how long
+/// the lock usually stays locked.
+const MAX_SPINS: u32 = 1000;
+
+/// A lock that spins for a while before going to sleep,
+/// adapting how long it spins to how long the lock is
+/// usually held, much like glibc's adaptive mutex.
+///
+/// Every contended acquisition that succeeds by spinning
+/// moves the estimate towards the number of spins it took,
+/// so short critical sections get a spin budget that's just
+/// long enough. Having to sleep means spinning was a waste,
+/// so the estimate shrinks instead.
+///
+/// Unlocking doesn't hand the lock over to a sleeping thread:
+/// the woken thread has to race for it like everybody else,
+/// and starts out by spinning again. A running thread can steal
+/// the lock in the meantime, which avoids lock convoys
+/// where every unlock has to wait for a thread to be scheduled.
+pub struct HybridLock<T> {
+    /// 0: unlocked
+    /// 1: locked, no other threads waiting
+    /// 2: locked, other threads (might be) waiting
+    state: AtomicU32,
+    /// Moving average of the spins it took to get the lock.
+    spin_estimate: AtomicU32,
+    lock_id: LockId,
+    stats: Recorder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for HybridLock<T> where T: Send {}
+
+impl<T> HybridLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            spin_estimate: AtomicU32::new(0),
+            lock_id: LockId::new(),
+            stats: Recorder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> HybridLockGuard<'_, T> {
+        self.lock_id.acquire();
+        let mut wait = self.stats.start_wait();
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+            .is_err()
+        {
+            self.lock_contended(&mut wait);
+        }
+        self.lock_id.acquired();
+        HybridLockGuard {
+            lock: self,
+            hold: self.stats.acquired(wait),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<HybridLockGuard<'_, T>> {
+        let wait = self.stats.start_wait();
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+            .ok()?;
+        self.lock_id.acquired();
+        Some(HybridLockGuard {
+            lock: self,
+            hold: self.stats.acquired(wait),
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state.load(Relaxed) != UNLOCKED
+    }
+
+    /// How many times a thread currently spins
+    /// before going to sleep.
+    pub fn spin_budget(&self) -> u32 {
+        (self.spin_estimate.load(Relaxed) * 2 + 10).min(MAX_SPINS)
+    }
+
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> super::stats::LockStats {
+        self.stats.snapshot()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    #[cold]
+    fn lock_contended(&self, wait_timer: &mut WaitTimer) {
+        wait_timer.spin();
+
+        // Until we've slept, there's no reason to think
+        // anybody else is waiting. Afterwards we can't know,
+        // so we have to leave the state at 2 (contended).
+        let mut locked = LOCKED;
+        loop {
+            let budget = self.spin_budget();
+            let mut spins = 0;
+            while spins < budget {
+                if self.state.load(Relaxed) == UNLOCKED
+                    && self
+                        .state
+                        .compare_exchange_weak(UNLOCKED, locked, Acquire, Relaxed)
+                        .is_ok()
+                {
+                    self.adapt(spins, true);
+                    return;
+                }
+                spins += 1;
+                wait_timer.spin();
+                spin_loop();
+            }
+            self.adapt(spins, false);
+
+            if self.state.swap(CONTENDED, Acquire) == UNLOCKED {
+                return;
+            }
+            wait_timer.spin();
+            wait(&self.state, CONTENDED);
+            locked = CONTENDED;
+        }
+    }
+
+    /// Moves the spin estimate an eighth of the way towards
+    /// what we've just seen, so one odd case doesn't throw it off.
+    fn adapt(&self, spins: u32, acquired: bool) {
+        let estimate = self.spin_estimate.load(Relaxed);
+        let new_estimate = if acquired {
+            if spins > estimate {
+                estimate + (spins - estimate).div_ceil(8)
+            } else {
+                estimate - (estimate - spins) / 8
+            }
+        } else {
+            estimate - estimate.div_ceil(8)
+        };
+        // Racing updates might get lost, which is fine for an estimate
+        self.spin_estimate
+            .store(new_estimate.min(MAX_SPINS), Relaxed);
+    }
+}
+
+pub struct HybridLockGuard<'a, T> {
+    lock: &'a HybridLock<T>,
+    hold: HoldTimer,
+}
+
+unsafe impl<T> Sync for HybridLockGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for HybridLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for HybridLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for HybridLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.stats.released(&self.hold);
+        self.lock.lock_id.release();
+        // The woken thread isn't handed the lock,
+        // it has to take it like anyone else.
+        if self.lock.state.swap(UNLOCKED, Release) == CONTENDED {
+            wake_one(&self.lock.state);
+        }
+    }
+}
+
+impl<T> Relock for HybridLockGuard<'_, T> {
+    fn relock_after(self, f: impl FnOnce()) -> Self {
+        let lock = self.lock;
+        drop(self);
+        f();
+        lock.lock()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::{HybridLock, MAX_SPINS};
+
+    #[test]
+    fn test_hybrid_lock_counter() {
+        let lock = HybridLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.into_inner(), 40_000);
+    }
+
+    #[test]
+    fn test_hybrid_lock_sleeps_on_long_hold() {
+        let lock = HybridLock::new(());
+        let guard = lock.lock();
+        thread::scope(|s| {
+            s.spawn(|| drop(lock.lock()));
+            thread::sleep(Duration::from_millis(20));
+            assert!(lock.try_lock().is_none());
+            drop(guard);
+        });
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn test_spin_budget_adapts() {
+        let lock = HybridLock::new(());
+        let initial = lock.spin_budget();
+
+        // Getting the lock after spinning a lot raises the budget
+        for _ in 0..50 {
+            lock.adapt(200, true);
+        }
+        let raised = lock.spin_budget();
+        assert!(raised > initial);
+        assert!(raised <= MAX_SPINS);
+
+        // Having to sleep anyway lowers it again
+        for _ in 0..50 {
+            lock.adapt(raised, false);
+        }
+        assert_eq!(lock.spin_budget(), initial);
+    }
+}
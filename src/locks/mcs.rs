@@ -0,0 +1,248 @@
+use std::{
+    cell::{RefCell, UnsafeCell},
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering::*},
+};
+
+use super::stats::{HoldTimer, Recorder};
+
+/// A waiter's place in the queue.
+struct Node {
+    /// Set to false by the previous holder
+    /// when it hands the lock over to us.
+    locked: AtomicBool,
+    next: AtomicPtr<Node>,
+}
+
+/// How many freed nodes a thread keeps around for reuse.
+const MAX_SPARE_NODES: usize = 8;
+
+thread_local! {
+    /// Nodes given back on this thread, so taking a lock
+    /// only needs to allocate when we've run out of them.
+    /// Boxed, since their addresses end up in other threads' nodes.
+    #[allow(clippy::vec_box)]
+    static SPARE_NODES: RefCell<Vec<Box<Node>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn new_node() -> NonNull<Node> {
+    let node = SPARE_NODES
+        .try_with(|spare| spare.borrow_mut().pop())
+        .ok()
+        .flatten()
+        .map(|mut node| {
+            *node.locked.get_mut() = true;
+            *node.next.get_mut() = ptr::null_mut();
+            node
+        })
+        .unwrap_or_else(|| {
+            Box::new(Node {
+                locked: AtomicBool::new(true),
+                next: AtomicPtr::new(ptr::null_mut()),
+            })
+        });
+    NonNull::from(Box::leak(node))
+}
+
+/// Safety: The node must come from `new_node`,
+/// and nobody else may be able to reach it anymore.
+unsafe fn free_node(node: NonNull<Node>) {
+    let node = Box::from_raw(node.as_ptr());
+    // If the thread local is already gone, the node is simply freed
+    let _ = SPARE_NODES.try_with(|spare| {
+        let mut spare = spare.borrow_mut();
+        if spare.len() < MAX_SPARE_NODES {
+            spare.push(node);
+        }
+    });
+}
+
+/// MCS queue lock.
+///
+/// Waiters line up in a linked list of nodes, and every waiter
+/// spins on the flag in its own node rather than on a flag shared
+/// by everybody. Unlocking only touches the next waiter's node,
+/// so the lock is handed out in FIFO order and the cache line
+/// traffic doesn't grow with the number of waiting threads.
+///
+/// Nodes live on the heap, but every thread keeps a few spare ones
+/// around, so only its first acquisitions have to allocate.
+pub struct McsLock<T> {
+    /// The last node in the queue, null when unlocked.
+    tail: AtomicPtr<Node>,
+    stats: Recorder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for McsLock<T> where T: Send {}
+
+impl<T> McsLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            stats: Recorder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        let mut wait = self.stats.start_wait();
+        let node = new_node();
+
+        // Release, so whoever queues up behind us sees an initialized node.
+        // Acquire, to see what the previous holder did if the lock was free.
+        let prev = self.tail.swap(node.as_ptr(), AcqRel);
+        if let Some(prev) = NonNull::new(prev) {
+            // Safety: The previous node can't be freed before
+            // it handed the lock over, which it can only do
+            // once we linked ourselves to it.
+            unsafe { prev.as_ref().next.store(node.as_ptr(), Release) };
+            // Safety: Nobody frees our node but our own Guard
+            while unsafe { node.as_ref() }.locked.load(Acquire) {
+                wait.spin();
+                spin_loop();
+            }
+        }
+
+        Guard {
+            lock: self,
+            node,
+            hold: self.stats.acquired(wait),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        !self.tail.load(Relaxed).is_null()
+    }
+
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> super::stats::LockStats {
+        self.stats.snapshot()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+/// Owns the queue node of the thread holding the lock,
+/// which is given back once the lock was handed over.
+pub struct Guard<'a, T> {
+    lock: &'a McsLock<T>,
+    node: NonNull<Node>,
+    hold: HoldTimer,
+}
+
+// The node is only shared with the threads queued up
+// right before and after us, through atomics.
+unsafe impl<T> Send for Guard<'_, T> where T: Send {}
+unsafe impl<T> Sync for Guard<'_, T> where T: Sync {}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we're at the head of the queue
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we're at the head of the queue
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.stats.released(&self.hold);
+        // Safety: The node stays alive until the end of this function
+        let node = unsafe { self.node.as_ref() };
+
+        let mut next = node.next.load(Acquire);
+        if next.is_null() {
+            // Nobody behind us, so try to leave the queue empty.
+            if self
+                .lock
+                .tail
+                .compare_exchange(self.node.as_ptr(), ptr::null_mut(), Release, Relaxed)
+                .is_ok()
+            {
+                // Safety: Not in the queue anymore, nobody else can reach it
+                unsafe { free_node(self.node) };
+                return;
+            }
+            // Somebody swapped the tail but didn't link
+            // themselves to our node yet, so wait for them.
+            loop {
+                next = node.next.load(Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                spin_loop();
+            }
+        }
+
+        // Safety: The next node stays alive at least until we hand it
+        // the lock, and it's the last time anybody touches our node.
+        unsafe {
+            (*next).locked.store(false, Release);
+            free_node(self.node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{hint::spin_loop, sync::atomic::Ordering::Relaxed, thread};
+
+    use super::McsLock;
+
+    #[test]
+    fn test_mcs_lock() {
+        let lock = McsLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert!(!lock.is_locked());
+        assert_eq!(lock.into_inner(), 400);
+    }
+
+    #[test]
+    fn test_mcs_lock_fifo() {
+        let lock = McsLock::new(Vec::new());
+
+        let guard = lock.lock();
+        thread::scope(|s| {
+            // Let the threads queue up one after another. Every new
+            // thread brings a new node, which becomes the tail.
+            for i in 0..3 {
+                let tail = lock.tail.load(Relaxed);
+                let lock = &lock;
+                s.spawn(move || lock.lock().push(i));
+                while lock.tail.load(Relaxed) == tail {
+                    spin_loop();
+                }
+            }
+            drop(guard);
+        });
+
+        assert_eq!(*lock.lock(), [0, 1, 2]);
+        assert!(!lock.is_locked());
+    }
+}
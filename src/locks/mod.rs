@@ -1 +1,15 @@
+pub mod async_mutex;
+pub mod backoff;
+pub mod condvar;
+mod deadlock;
+pub mod hybrid;
+pub mod mcs;
+pub mod mutex;
+pub mod poison;
+pub mod reentrant;
+pub mod rwlock;
+pub mod semaphore;
+pub mod seqlock;
 pub mod spin_lock;
+pub mod stats;
+pub mod ticket_lock;
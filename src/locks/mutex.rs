@@ -0,0 +1,167 @@
+use std::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering::*},
+};
+
+use crate::atomic_wait::{wait, wake_one};
+
+use super::{
+    condvar::Relock,
+    deadlock::LockId,
+    stats::{HoldTimer, Recorder, WaitTimer},
+};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2;
+
+/// A mutex that puts blocked threads to sleep
+/// instead of spinning forever like the SpinLock.
+///
+/// The state keeps track of whether there might be
+/// waiting threads, so unlocking only needs a syscall
+/// when somebody is actually asleep.
+pub struct Mutex<T> {
+    /// 0: unlocked
+    /// 1: locked, no other threads waiting
+    /// 2: locked, other threads (might be) waiting
+    state: AtomicU32,
+    lock_id: LockId,
+    stats: Recorder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            lock_id: LockId::new(),
+            stats: Recorder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.lock_id.acquire();
+        let mut wait = self.stats.start_wait();
+        // Uncontended path: a single compare-and-exchange
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+            .is_err()
+        {
+            lock_contended(&self.state, &mut wait);
+        }
+        self.lock_id.acquired();
+        MutexGuard {
+            mutex: self,
+            hold: self.stats.acquired(wait),
+        }
+    }
+
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> super::stats::LockStats {
+        self.stats.snapshot()
+    }
+}
+
+/// Called after the first compare-and-exchange failed.
+#[cold]
+pub(super) fn lock_contended(state: &AtomicU32, wait_timer: &mut WaitTimer) {
+    wait_timer.spin();
+
+    // Spin for a little while first, in case the lock
+    // is released soon. We only spin while nobody is waiting,
+    // otherwise we'd just be stealing CPU from the others.
+    let mut spin_count = 0;
+    while state.load(Relaxed) == LOCKED && spin_count < 100 {
+        spin_count += 1;
+        wait_timer.spin();
+        spin_loop();
+    }
+
+    if state
+        .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+        .is_ok()
+    {
+        return;
+    }
+
+    // From now on we always set the state to 2 (contended),
+    // since we can't know whether there are other waiters left.
+    while state.swap(CONTENDED, Acquire) != UNLOCKED {
+        wait_timer.spin();
+        wait(state, CONTENDED);
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    hold: HoldTimer,
+}
+
+unsafe impl<T> Sync for MutexGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the mutex
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the mutex
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.stats.released(&self.hold);
+        self.mutex.lock_id.release();
+        // Only wake a thread up if somebody might be waiting
+        if self.mutex.state.swap(UNLOCKED, Release) == CONTENDED {
+            wake_one(&self.mutex.state);
+        }
+    }
+}
+
+impl<T> Relock for MutexGuard<'_, T> {
+    fn relock_after(self, f: impl FnOnce()) -> Self {
+        let mutex = self.mutex;
+        drop(self);
+        f();
+        mutex.lock()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::Mutex;
+
+    #[test]
+    fn test_mutex_counter() {
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *m.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(*m.lock(), 40_000);
+    }
+}
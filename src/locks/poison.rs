@@ -0,0 +1,99 @@
+use std::{error::Error, fmt};
+
+/// Returned by `lock()` on a poisoning lock when a thread
+/// panicked while holding it. The lock is still taken,
+/// and `into_inner` gets you the guard anyway
+/// if you know how to deal with the state it's in.
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+impl<G> PoisonError<G> {
+    pub fn new(guard: G) -> Self {
+        Self { guard }
+    }
+
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+// Not derived, so it also works for guards that aren't Debug
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "poisoned lock: another task failed inside".fmt(f)
+    }
+}
+
+impl<G> Error for PoisonError<G> {}
+
+/// Decides whether a lock keeps track of panics
+/// and what its `lock()` method returns.
+pub trait Poisoning {
+    const ENABLED: bool;
+
+    /// What `lock()` hands out for a guard of type `G`.
+    type LockResult<G>;
+
+    fn wrap<G>(poisoned: bool, guard: G) -> Self::LockResult<G>;
+
+    /// Gets the guard out, no matter if the lock was poisoned.
+    fn into_guard<G>(result: Self::LockResult<G>) -> G;
+}
+
+/// Panics are ignored and `lock()` returns the guard itself.
+#[derive(Debug)]
+pub struct NoPoison;
+
+impl Poisoning for NoPoison {
+    const ENABLED: bool = false;
+
+    type LockResult<G> = G;
+
+    fn wrap<G>(_poisoned: bool, guard: G) -> G {
+        guard
+    }
+
+    fn into_guard<G>(guard: G) -> G {
+        guard
+    }
+}
+
+/// Panicking while holding a guard poisons the lock,
+/// and `lock()` returns a `LockResult`, just like std's Mutex.
+#[derive(Debug)]
+pub struct Poison;
+
+impl Poisoning for Poison {
+    const ENABLED: bool = true;
+
+    type LockResult<G> = LockResult<G>;
+
+    fn wrap<G>(poisoned: bool, guard: G) -> LockResult<G> {
+        if poisoned {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    fn into_guard<G>(result: LockResult<G>) -> G {
+        result.unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::Deref,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering::*},
+};
+
+use crate::atomic_wait::wake_one;
+
+use super::{
+    mutex::lock_contended,
+    stats::{HoldTimer, Recorder},
+};
+
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    // Unlike the address of a thread local, these
+    // ids are never reused once a thread exits.
+    static THREAD_ID: usize = NEXT_THREAD_ID.fetch_add(1, Relaxed);
+}
+
+fn current_thread_id() -> usize {
+    THREAD_ID.with(|id| *id)
+}
+
+/// A lock that can be taken again by the thread
+/// that's already holding it, instead of deadlocking.
+///
+/// Since the same value might be borrowed through several guards
+/// at once, a guard only gives shared access. Use interior
+/// mutability (e.g. a RefCell) to change the value.
+pub struct ReentrantLock<T> {
+    /// Same states as the Mutex:
+    /// 0: unlocked
+    /// 1: locked, no other threads waiting
+    /// 2: locked, other threads (might be) waiting
+    state: AtomicU32,
+    /// Id of the thread holding the lock, 0 if none.
+    owner: AtomicUsize,
+    /// How many guards the owner currently has.
+    /// Only ever touched by the owning thread.
+    count: UnsafeCell<u32>,
+    stats: Recorder,
+    value: T,
+}
+
+// Only one thread at a time can get at the value,
+// so T doesn't need to be Sync.
+unsafe impl<T> Sync for ReentrantLock<T> where T: Send {}
+
+impl<T> ReentrantLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            owner: AtomicUsize::new(0),
+            count: UnsafeCell::new(0),
+            stats: Recorder::new(),
+            value,
+        }
+    }
+
+    pub fn lock(&self) -> ReentrantLockGuard<'_, T> {
+        let mut wait = self.stats.start_wait();
+        let this_thread = current_thread_id();
+        // Relaxed is enough: only this very thread
+        // could have stored its own id in there.
+        if self.owner.load(Relaxed) == this_thread {
+            self.increment_count();
+        } else {
+            if self.state.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
+                lock_contended(&self.state, &mut wait);
+            }
+            self.owner.store(this_thread, Relaxed);
+            // Safety: We own the lock now
+            unsafe { *self.count.get() = 1 };
+        }
+        ReentrantLockGuard {
+            lock: self,
+            hold: self.stats.acquired(wait),
+            _no_send: PhantomData,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<ReentrantLockGuard<'_, T>> {
+        let wait = self.stats.start_wait();
+        let this_thread = current_thread_id();
+        if self.owner.load(Relaxed) == this_thread {
+            self.increment_count();
+        } else if self.state.compare_exchange(0, 1, Acquire, Relaxed).is_ok() {
+            self.owner.store(this_thread, Relaxed);
+            // Safety: We own the lock now
+            unsafe { *self.count.get() = 1 };
+        } else {
+            return None;
+        }
+        Some(ReentrantLockGuard {
+            lock: self,
+            hold: self.stats.acquired(wait),
+            _no_send: PhantomData,
+        })
+    }
+
+    fn increment_count(&self) {
+        // Safety: Only called by the owning thread
+        let count = unsafe { &mut *self.count.get() };
+        *count = count
+            .checked_add(1)
+            .expect("lock count overflow in reentrant lock");
+    }
+
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> super::stats::LockStats {
+        self.stats.snapshot()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+pub struct ReentrantLockGuard<'a, T> {
+    lock: &'a ReentrantLock<T>,
+    hold: HoldTimer,
+    // The lock belongs to the thread that took it,
+    // so the guard can't be sent to another thread.
+    _no_send: PhantomData<*const ()>,
+}
+
+unsafe impl<T> Sync for ReentrantLockGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for ReentrantLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.lock.value
+    }
+}
+
+impl<T> Drop for ReentrantLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.stats.released(&self.hold);
+        // Safety: Guards only exist on the owning thread
+        let count = unsafe { &mut *self.lock.count.get() };
+        *count -= 1;
+        if *count == 0 {
+            self.lock.owner.store(0, Relaxed);
+            if self.lock.state.swap(0, Release) == 2 {
+                wake_one(&self.lock.state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, thread};
+
+    use super::ReentrantLock;
+
+    #[test]
+    fn test_reentrant_lock() {
+        let lock = ReentrantLock::new(RefCell::new(Vec::new()));
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let lock = &lock;
+                s.spawn(move || {
+                    let outer = lock.lock();
+                    // Would deadlock with any other lock in this crate
+                    let inner = lock.lock();
+                    inner.borrow_mut().push(i);
+                    drop(inner);
+                    outer.borrow_mut().push(i);
+                });
+            }
+        });
+
+        let list = lock.into_inner().into_inner();
+        assert_eq!(list.len(), 8);
+        // Both pushes happened while holding the lock
+        for pair in list.chunks(2) {
+            assert_eq!(pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_reentrant_try_lock() {
+        let lock = ReentrantLock::new(());
+        let _guard = lock.lock();
+        assert!(lock.try_lock().is_some());
+
+        thread::scope(|s| {
+            s.spawn(|| assert!(lock.try_lock().is_none()));
+        });
+    }
+}
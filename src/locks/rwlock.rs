@@ -0,0 +1,465 @@
+use std::{
+    cell::UnsafeCell,
+    mem,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering::*},
+};
+
+use crate::atomic_wait::{wait, wake_all, wake_one};
+
+use super::{
+    mutex::lock_contended,
+    stats::{HoldTimer, Recorder, WaitTimer},
+};
+
+const WRITE_LOCKED: u32 = u32::MAX;
+
+/// A reader-writer lock that blocks instead of spinning.
+///
+/// As soon as a writer is waiting, new readers are held back
+/// until the writer got its turn, so a steady stream
+/// of readers can't starve the writers.
+pub struct RwLock<T> {
+    /// The number of read locks times two,
+    /// plus one if there's a writer waiting.
+    /// u32::MAX if write locked.
+    ///
+    /// Readers only get in while this is even.
+    state: AtomicU32,
+    /// Incremented to wake up writers.
+    writer_wake_counter: AtomicU32,
+    /// Only one upgradable reader at a time, using
+    /// the same states as the Mutex:
+    /// 0: free
+    /// 1: taken, nobody else waiting
+    /// 2: taken, others (might be) waiting
+    upgradable: AtomicU32,
+    stats: Recorder,
+    value: UnsafeCell<T>,
+}
+
+// Readers share the value across threads,
+// so T needs to be Sync as well.
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            writer_wake_counter: AtomicU32::new(0),
+            upgradable: AtomicU32::new(0),
+            stats: Recorder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut wait = self.stats.start_wait();
+        self.read_lock(&mut wait);
+        ReadGuard {
+            rwlock: self,
+            hold: self.stats.acquired(wait),
+        }
+    }
+
+    /// A read lock that can later be upgraded to a write lock,
+    /// without letting another writer in between.
+    ///
+    /// Plain readers can still get in, but there's
+    /// at most one upgradable reader at a time.
+    pub fn upgradable_read(&self) -> UpgradableReadGuard<'_, T> {
+        let mut wait = self.stats.start_wait();
+        if self
+            .upgradable
+            .compare_exchange(0, 1, Acquire, Relaxed)
+            .is_err()
+        {
+            lock_contended(&self.upgradable, &mut wait);
+        }
+        self.read_lock(&mut wait);
+        UpgradableReadGuard {
+            rwlock: self,
+            hold: self.stats.acquired(wait),
+        }
+    }
+
+    fn read_lock(&self, wait_timer: &mut WaitTimer) {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            if s.is_multiple_of(2) {
+                assert!(s < WRITE_LOCKED - 2, "too many readers");
+                match self.state.compare_exchange_weak(s, s + 2, Acquire, Relaxed) {
+                    Ok(_) => return,
+                    Err(e) => s = e,
+                }
+            }
+            // Either write locked or a writer is waiting
+            if !s.is_multiple_of(2) {
+                wait_timer.spin();
+                wait(&self.state, s);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let mut wait_timer = self.stats.start_wait();
+        let mut s = self.state.load(Relaxed);
+        loop {
+            // Try to lock if unlocked
+            // (possibly with other writers waiting).
+            if s <= 1 {
+                match self
+                    .state
+                    .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => {
+                        return WriteGuard {
+                            rwlock: self,
+                            hold: self.stats.acquired(wait_timer),
+                        }
+                    }
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+
+            // Block new readers by making sure the state is odd.
+            if s.is_multiple_of(2) {
+                if let Err(e) = self.state.compare_exchange(s, s + 1, Relaxed, Relaxed) {
+                    s = e;
+                    continue;
+                }
+            }
+
+            // Wait, if it's still locked
+            let w = self.writer_wake_counter.load(Acquire);
+            s = self.state.load(Relaxed);
+            if s >= 2 {
+                wait_timer.spin();
+                wait(&self.writer_wake_counter, w);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> super::stats::LockStats {
+        self.stats.snapshot()
+    }
+
+    fn read_unlock(&self) {
+        let s = self.state.fetch_sub(2, Release);
+        if s == 3 {
+            // Going from 3 to 1 means we were the last reader
+            // and there's a writer waiting, which we wake up.
+            self.writer_wake_counter.fetch_add(1, Release);
+            wake_one(&self.writer_wake_counter);
+        } else if s == 5 {
+            // Going from 5 to 3 might leave an upgradable reader
+            // that's waiting to upgrade as the only one left.
+            // We can't tell it apart from the writers, so wake everybody.
+            self.writer_wake_counter.fetch_add(1, Release);
+            wake_all(&self.writer_wake_counter);
+        }
+    }
+
+    fn write_unlock(&self) {
+        self.state.store(0, Release);
+        // We don't know who's waiting, so wake up
+        // one writer and all of the readers.
+        self.writer_wake_counter.fetch_add(1, Release);
+        wake_one(&self.writer_wake_counter);
+        wake_all(&self.state);
+    }
+
+    fn upgradable_unlock(&self) {
+        if self.upgradable.swap(0, Release) == 2 {
+            wake_one(&self.upgradable);
+        }
+    }
+}
+
+/// Shared access only, there might be other readers around.
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+    hold: HoldTimer,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: While a ReadGuard exists nobody
+        // can get a hold of a WriteGuard
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.stats.released(&self.hold);
+        self.rwlock.read_unlock();
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+    hold: HoldTimer,
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the rwlock
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the rwlock
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.stats.released(&self.hold);
+        self.rwlock.write_unlock();
+    }
+}
+
+/// Shared access, plus the right to upgrade to exclusive access.
+pub struct UpgradableReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+    hold: HoldTimer,
+}
+
+impl<'a, T> UpgradableReadGuard<'a, T> {
+    /// Waits for the other readers to leave. New readers are
+    /// held back meanwhile, and no writer can get in first
+    /// since we never give up our own read lock.
+    pub fn upgrade(self) -> UpgradedWriteGuard<'a, T> {
+        let (rwlock, hold) = (self.rwlock, self.hold);
+        // The read lock turns into the write lock,
+        // the upgraded guard takes it from here.
+        mem::forget(self);
+
+        let mut s = rwlock.state.load(Relaxed);
+        loop {
+            // 2 or 3: we're the only reader left,
+            // possibly with writers waiting.
+            if s <= 3 {
+                match rwlock
+                    .state
+                    .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => return UpgradedWriteGuard { rwlock, hold },
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+
+            // Block new readers, just like a waiting writer.
+            if s.is_multiple_of(2) {
+                if let Err(e) = rwlock.state.compare_exchange(s, s + 1, Relaxed, Relaxed) {
+                    s = e;
+                    continue;
+                }
+            }
+
+            // Readers leaving wake us up through the writer counter
+            let w = rwlock.writer_wake_counter.load(Acquire);
+            s = rwlock.state.load(Relaxed);
+            if s > 3 {
+                wait(&rwlock.writer_wake_counter, w);
+                s = rwlock.state.load(Relaxed);
+            }
+        }
+    }
+
+    /// Upgrades only if there are no other readers right now.
+    pub fn try_upgrade(self) -> Result<UpgradedWriteGuard<'a, T>, Self> {
+        let mut s = self.rwlock.state.load(Relaxed);
+        while s <= 3 {
+            match self
+                .rwlock
+                .state
+                .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+            {
+                Ok(_) => {
+                    let (rwlock, hold) = (self.rwlock, self.hold);
+                    mem::forget(self);
+                    return Ok(UpgradedWriteGuard { rwlock, hold });
+                }
+                Err(e) => s = e,
+            }
+        }
+        Err(self)
+    }
+}
+
+impl<T> Deref for UpgradableReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: We hold a read lock,
+        // so there's no writer around
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for UpgradableReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.stats.released(&self.hold);
+        self.rwlock.read_unlock();
+        self.rwlock.upgradable_unlock();
+    }
+}
+
+/// A write lock that came out of `UpgradableReadGuard::upgrade`
+/// and can be downgraded back into it.
+pub struct UpgradedWriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+    hold: HoldTimer,
+}
+
+impl<'a, T> UpgradedWriteGuard<'a, T> {
+    /// Turns the write lock back into an upgradable read lock,
+    /// letting the other readers in again.
+    pub fn downgrade(self) -> UpgradableReadGuard<'a, T> {
+        let (rwlock, hold) = (self.rwlock, self.hold);
+        mem::forget(self);
+
+        rwlock.state.store(2, Release);
+        // Writers that were waiting lost their "writer waiting" bit,
+        // so wake them up to set it again. Readers can come in now.
+        rwlock.writer_wake_counter.fetch_add(1, Release);
+        wake_all(&rwlock.writer_wake_counter);
+        wake_all(&rwlock.state);
+
+        UpgradableReadGuard { rwlock, hold }
+    }
+}
+
+impl<T> Deref for UpgradedWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the rwlock
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for UpgradedWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the rwlock
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for UpgradedWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.stats.released(&self.hold);
+        self.rwlock.write_unlock();
+        self.rwlock.upgradable_unlock();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::RwLock;
+
+    #[test]
+    fn test_rwlock() {
+        let lock = RwLock::new(Vec::new());
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let lock = &lock;
+                s.spawn(move || {
+                    for j in 0..100 {
+                        lock.write().push(i * 100 + j);
+                    }
+                });
+            }
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        let list = lock.read();
+                        // A reader never sees a half-done push
+                        assert!(list.len() <= 400);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(lock.read().len(), 400);
+    }
+
+    #[test]
+    fn test_upgradable_read() {
+        let lock = RwLock::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        // Check-then-modify without a lost update
+                        let guard = lock.upgradable_read();
+                        let value = *guard;
+                        let mut guard = guard.upgrade();
+                        *guard = value + 1;
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..100 {
+                    assert!(*lock.read() <= 400);
+                }
+            });
+        });
+
+        assert_eq!(*lock.read(), 400);
+    }
+
+    #[test]
+    fn test_try_upgrade_and_downgrade() {
+        let lock = RwLock::new(1);
+
+        let upgradable = lock.upgradable_read();
+        let reader = lock.read();
+        let Err(upgradable) = upgradable.try_upgrade() else {
+            panic!("another reader is still around");
+        };
+        drop(reader);
+
+        let mut writer = upgradable.try_upgrade().ok().unwrap();
+        *writer += 1;
+        let upgradable = writer.downgrade();
+
+        // Plain readers are welcome again
+        assert_eq!(*lock.read(), 2);
+        assert_eq!(*upgradable, 2);
+    }
+
+    #[test]
+    fn test_rwlock_multiple_readers() {
+        let lock = RwLock::new(1);
+        let a = lock.read();
+        let b = lock.read();
+        assert_eq!(*a + *b, 2);
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering::*},
+    time::{Duration, Instant},
+};
+
+use crate::atomic_wait::{wait_timeout, wake_all};
+
+/// A counting semaphore, handing out a limited number of permits
+/// to limit how many threads can do something at the same time.
+///
+/// Threads that can't get enough permits go to sleep
+/// until somebody gives some back. It's not fair: a thread
+/// asking for a lot of permits can be overtaken by others
+/// asking for fewer.
+pub struct Semaphore {
+    permits: AtomicU32,
+    /// Number of sleeping threads, so giving back
+    /// permits only needs a syscall when somebody sleeps.
+    waiters: AtomicU32,
+}
+
+impl Semaphore {
+    pub const fn new(permits: u32) -> Self {
+        Self {
+            permits: AtomicU32::new(permits),
+            waiters: AtomicU32::new(0),
+        }
+    }
+
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Waits until `n` permits are available and takes them all at once.
+    pub fn acquire_many(&self, n: u32) -> SemaphorePermit<'_> {
+        loop {
+            if let Some(permit) = self.try_acquire_many(n) {
+                return permit;
+            }
+            self.sleep(n, None);
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    fn try_acquire_many(&self, n: u32) -> Option<SemaphorePermit<'_>> {
+        self.permits
+            .fetch_update(Acquire, Relaxed, |p| p.checked_sub(n))
+            .ok()
+            .map(|_| SemaphorePermit {
+                semaphore: self,
+                count: n,
+            })
+    }
+
+    /// Like `acquire`, but gives up and returns None
+    /// if no permit became available within `timeout`.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Option<SemaphorePermit<'_>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(permit) = self.try_acquire() {
+                return Some(permit);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            self.sleep(1, Some(remaining));
+        }
+    }
+
+    /// Sleeps until there might be at least `n` permits.
+    fn sleep(&self, n: u32, timeout: Option<Duration>) {
+        // SeqCst, paired with the one in add_permits: either we see
+        // the new permits, or they see us waiting and wake us up.
+        self.waiters.fetch_add(1, SeqCst);
+        let p = self.permits.load(SeqCst);
+        if p < n {
+            wait_timeout(&self.permits, p, timeout);
+        }
+        self.waiters.fetch_sub(1, Relaxed);
+    }
+
+    /// Adds permits, which can also be used to hand out more
+    /// permits than the semaphore was created with.
+    pub fn add_permits(&self, n: u32) {
+        self.permits
+            .fetch_update(SeqCst, Relaxed, |p| p.checked_add(n))
+            .expect("too many permits in semaphore");
+        // Waking everybody up, since we don't know how many
+        // permits each of them needs. Those that can't
+        // get enough just go back to sleep.
+        if self.waiters.load(SeqCst) > 0 {
+            wake_all(&self.permits);
+        }
+    }
+
+    /// Number of permits available right now. Only a snapshot,
+    /// it might have changed by the time you look at it.
+    pub fn available_permits(&self) -> u32 {
+        self.permits.load(Relaxed)
+    }
+}
+
+/// Gives its permits back to the semaphore when dropped.
+#[must_use = "the permits are given back right away if the permit is dropped"]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    count: u32,
+}
+
+impl SemaphorePermit<'_> {
+    /// Number of permits held by this permit.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.count);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering::Relaxed},
+        thread,
+        time::Duration,
+    };
+
+    use super::Semaphore;
+
+    #[test]
+    fn test_semaphore_limits_concurrency() {
+        let semaphore = Semaphore::new(2);
+        let inside = AtomicU32::new(0);
+        let max_inside = AtomicU32::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..6 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        let _permit = semaphore.acquire();
+                        let n = inside.fetch_add(1, Relaxed) + 1;
+                        max_inside.fetch_max(n, Relaxed);
+                        thread::sleep(Duration::from_millis(1));
+                        inside.fetch_sub(1, Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(max_inside.load(Relaxed), 2);
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[test]
+    fn test_acquire_many() {
+        let semaphore = Semaphore::new(3);
+
+        let one = semaphore.acquire();
+        assert!(semaphore.try_acquire().is_some());
+        thread::scope(|s| {
+            let t = s.spawn(|| semaphore.acquire_many(3).count());
+            thread::sleep(Duration::from_millis(20));
+            assert!(!t.is_finished());
+            drop(one);
+            assert_eq!(t.join().unwrap(), 3);
+        });
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn test_acquire_timeout() {
+        let semaphore = Semaphore::new(0);
+        assert!(semaphore.try_acquire().is_none());
+        assert!(semaphore
+            .acquire_timeout(Duration::from_millis(10))
+            .is_none());
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                semaphore.add_permits(1);
+            });
+            assert!(semaphore.acquire_timeout(Duration::from_secs(10)).is_some());
+        });
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{fence, AtomicUsize, Ordering::*},
+};
+
+/// A sequence lock for small Copy values.
+///
+/// Writers bump the sequence number to an odd value,
+/// write the new value and bump it to even again.
+/// Readers never block anybody: they copy the value
+/// optimistically and retry if the sequence number
+/// changed in the meantime, which means they might
+/// have seen a half-written (torn) value.
+pub struct SeqLock<T: Copy> {
+    /// Odd while a write is in progress.
+    seq: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SeqLock<T> where T: Copy + Send {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> T {
+        loop {
+            let before = self.seq.load(Acquire);
+            if before % 2 == 1 {
+                // A write is in progress, no point in copying yet
+                spin_loop();
+                continue;
+            }
+
+            // Safety: This might race with a writer, which is why
+            // we copy into a MaybeUninit and only look at the result
+            // once we know no write happened during the copy.
+            let value = unsafe { ptr::read_volatile(self.value.get() as *const MaybeUninit<T>) };
+
+            // Keeps the copy above from being moved past
+            // the second load of the sequence number.
+            fence(Acquire);
+            if self.seq.load(Relaxed) == before {
+                // Safety: Nothing was written while we made the copy
+                return unsafe { value.assume_init() };
+            }
+        }
+    }
+
+    /// Never waits for readers, only for another
+    /// writer that might be in the middle of a write.
+    pub fn write(&self, value: T) {
+        let mut seq = self.seq.load(Relaxed);
+        loop {
+            if seq % 2 == 1 {
+                spin_loop();
+                seq = self.seq.load(Relaxed);
+                continue;
+            }
+            match self
+                .seq
+                .compare_exchange_weak(seq, seq + 1, Acquire, Relaxed)
+            {
+                Ok(_) => break,
+                Err(e) => seq = e,
+            }
+        }
+
+        // Makes sure readers see the odd sequence number
+        // before they can see any part of the new value.
+        fence(Release);
+        // Safety: Other writers are kept out by the odd sequence
+        // number and readers throw away whatever they copied meanwhile.
+        unsafe { ptr::write_volatile(self.value.get(), value) };
+        self.seq.store(seq + 2, Release);
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering::Relaxed},
+        thread,
+    };
+
+    use super::SeqLock;
+
+    #[test]
+    fn test_seqlock_no_torn_reads() {
+        // Every element is always the same, so a torn read
+        // would show up as a mix of old and new elements.
+        let lock = SeqLock::new([0u64; 16]);
+        let done = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    while !done.load(Relaxed) {
+                        let value = lock.read();
+                        assert!(value.iter().all(|v| *v == value[0]), "torn read: {value:?}");
+                    }
+                });
+            }
+
+            for i in 1..=10_000 {
+                lock.write([i; 16]);
+            }
+            done.store(true, Relaxed);
+        });
+
+        assert_eq!(lock.read(), [10_000; 16]);
+    }
+
+    #[test]
+    fn test_seqlock_multiple_writers() {
+        let lock = SeqLock::new((0u32, u32::MAX));
+
+        thread::scope(|s| {
+            for t in 0..4 {
+                let lock = &lock;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        let v = t * 1000 + i;
+                        lock.write((v, !v));
+                        let (a, b) = lock.read();
+                        assert_eq!(a, !b);
+                    }
+                });
+            }
+        });
+    }
+}
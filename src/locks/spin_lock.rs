@@ -1,27 +1,107 @@
 use std::{
     cell::UnsafeCell,
     hint::spin_loop,
+    marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
+    ptr::NonNull,
     sync::atomic::AtomicBool,
+    thread,
+    time::{Duration, Instant},
 };
 
-pub struct SpinLock<T> {
-    locked: AtomicBool,
+use super::{
+    backoff::{Backoff, SpinLoop},
+    condvar::Relock,
+    deadlock::LockId,
+    poison::{NoPoison, Poison, Poisoning},
+    stats::{HoldTimer, Recorder, WaitTimer},
+};
+
+/// `B` decides what happens after a failed attempt at taking
+/// the lock, see the `backoff` module for the built-in strategies.
+///
+/// `P` opts into poisoning: with `Poison`, a thread panicking
+/// while holding the lock marks it as poisoned and `lock()`
+/// returns a `LockResult` instead of the guard itself.
+pub struct SpinLock<T, B = SpinLoop, P = NoPoison> {
+    raw: RawSpinLock,
     value: UnsafeCell<T>,
+    // fn() -> (B, P) keeps SpinLock Send and Sync
+    // no matter what the strategy types are.
+    _strategy: PhantomData<fn() -> (B, P)>,
 }
 
-unsafe impl<T> Sync for SpinLock<T> where T: Send {}
+unsafe impl<T, B, P> Sync for SpinLock<T, B, P> where T: Send {}
+
+/// Everything but the value, so a MappedGuard
+/// can unlock without knowing about T, B or P.
+struct RawSpinLock {
+    locked: AtomicBool,
+    poisoned: AtomicBool,
+    lock_id: LockId,
+    stats: Recorder,
+}
+
+impl RawSpinLock {
+    fn release(&self, poison_on_panic: bool, hold: &HoldTimer) {
+        if poison_on_panic && thread::panicking() {
+            self.poisoned
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        self.stats.released(hold);
+        self.lock_id.release();
+        self.locked
+            .store(false, std::sync::atomic::Ordering::Release)
+    }
+}
+
+/// What `lock()` returns, which depends on `P`.
+pub type SpinLockResult<'a, T, B, P> = <P as Poisoning>::LockResult<Guard<'a, T, B, P>>;
 
 impl<T> SpinLock<T> {
     pub const fn new(value: T) -> Self {
+        Self::with_backoff(value)
+    }
+}
+
+impl<T> SpinLock<T, SpinLoop, Poison> {
+    /// A SpinLock that gets poisoned when
+    /// a thread panics while holding it.
+    pub const fn poisoning(value: T) -> Self {
+        Self::with_backoff(value)
+    }
+}
+
+impl<T, B: Backoff, P: Poisoning> SpinLock<T, B, P> {
+    /// Same as `new`, for a SpinLock with a different
+    /// backoff strategy (or poisoning), e.g.
+    /// `SpinLock::<_, Exponential>::with_backoff(value)`.
+    pub const fn with_backoff(value: T) -> Self {
         Self {
-            locked: AtomicBool::new(false),
+            raw: RawSpinLock {
+                locked: AtomicBool::new(false),
+                poisoned: AtomicBool::new(false),
+                lock_id: LockId::new(),
+                stats: Recorder::new(),
+            },
             value: UnsafeCell::new(value),
+            _strategy: PhantomData,
         }
     }
 
-    pub fn lock(&self) -> Guard<T> {
+    pub fn lock(&self) -> SpinLockResult<'_, T, B, P> {
+        let hold = self.raw_lock();
+        self.guard(hold)
+    }
+
+    /// Spins until the lock is ours, without making a Guard.
+    fn raw_lock(&self) -> HoldTimer {
+        self.raw.lock_id.acquire();
+        let mut wait = self.raw.stats.start_wait();
+        let mut backoff = B::default();
         while self
+            .raw
             .locked
             .compare_exchange_weak(
                 false,
@@ -31,27 +111,153 @@ impl<T> SpinLock<T> {
             )
             .is_err()
         {
-            spin_loop();
+            wait.spin();
+            backoff.snooze(&self.raw.locked);
         }
-        Guard { lock: self }
+        self.raw.lock_id.acquired();
+        self.raw.stats.acquired(wait)
     }
 
-    pub fn unlock(&self) {
-        self.locked
+    /// Only called once the lock is taken.
+    fn guard(&self, hold: HoldTimer) -> SpinLockResult<'_, T, B, P> {
+        let guard = Guard {
+            lock: self,
+            hold,
+            // A guard taken while already unwinding
+            // isn't to blame for the panic.
+            poison_on_panic: P::ENABLED && !thread::panicking(),
+        };
+        P::wrap(
+            P::ENABLED && self.raw.poisoned.load(std::sync::atomic::Ordering::Relaxed),
+            guard,
+        )
+    }
+
+    /// Takes the lock only if it is free right now.
+    pub fn try_lock(&self) -> Option<SpinLockResult<'_, T, B, P>> {
+        let wait = self.raw.stats.start_wait();
+        self.try_acquire().then(|| self.acquired(wait))
+    }
+
+    /// Keeps spinning for at most `timeout` before giving up.
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<SpinLockResult<'_, T, B, P>> {
+        self.try_lock_until(Instant::now() + timeout)
+    }
+
+    /// Keeps spinning until `deadline` before giving up.
+    /// The lock is always tried at least once,
+    /// even if the deadline is already in the past.
+    pub fn try_lock_until(&self, deadline: Instant) -> Option<SpinLockResult<'_, T, B, P>> {
+        // One timer for the whole attempt, so the stats
+        // see all the time we spent spinning.
+        let mut wait = self.raw.stats.start_wait();
+        loop {
+            if self.try_acquire() {
+                return Some(self.acquired(wait));
+            }
+            wait.spin();
+            // Wait until the lock looks free before trying the
+            // compare-and-exchange again, checking the clock in between.
+            while self.is_locked() {
+                if Instant::now() >= deadline {
+                    return None;
+                }
+                wait.spin();
+                spin_loop();
+            }
+        }
+    }
+
+    /// A single attempt at taking the lock, without blocking.
+    fn try_acquire(&self) -> bool {
+        self.raw
+            .locked
+            .compare_exchange(
+                false,
+                true,
+                std::sync::atomic::Ordering::Acquire,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Makes the Guard once `try_acquire` took the lock.
+    fn acquired(&self, wait: WaitTimer) -> SpinLockResult<'_, T, B, P> {
+        self.raw.lock_id.acquired();
+        self.guard(self.raw.stats.acquired(wait))
+    }
+
+    /// Only a hint, the lock might have been taken
+    /// or released by the time you look at the result.
+    pub fn is_locked(&self) -> bool {
+        self.raw.locked.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Releases the lock without a Guard.
+    ///
+    /// Meant for cases where the Guard was forgotten with
+    /// `mem::forget`, e.g. to keep the lock across an FFI boundary.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held by the current context through a Guard
+    /// that was forgotten, and no Guard for this lock may be alive
+    /// anymore. Otherwise the value could be accessed by two threads at once.
+    pub unsafe fn force_unlock(&self) {
+        self.raw.lock_id.release();
+        self.raw
+            .locked
             .store(false, std::sync::atomic::Ordering::Release)
     }
+
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> super::stats::LockStats {
+        self.raw.stats.snapshot()
+    }
+
+    /// No locking needed, owning the SpinLock
+    /// means nobody else can have a Guard.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// No locking needed either, the exclusive borrow
+    /// guarantees there's no Guard around.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
 }
 
-pub struct Guard<'a, T> {
-    lock: &'a SpinLock<T>,
+impl<T, B> SpinLock<T, B, Poison> {
+    pub fn is_poisoned(&self) -> bool {
+        self.raw.poisoned.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// For when the value was put back into a consistent
+    /// state after recovering from a `PoisonError`.
+    pub fn clear_poison(&self) {
+        self.raw
+            .poisoned
+            .store(false, std::sync::atomic::Ordering::Relaxed)
+    }
 }
 
+pub struct Guard<'a, T, B = SpinLoop, P = NoPoison> {
+    lock: &'a SpinLock<T, B, P>,
+    hold: HoldTimer,
+    /// Set if poisoning is enabled and the thread
+    /// wasn't already panicking when it took the lock.
+    poison_on_panic: bool,
+}
+
+unsafe impl<T, B, P> Sync for Guard<'_, T, B, P> where T: Sync {}
+
 // Deref works as a proxy here by providing
 // a controled unsafe interface access to the actual lock value.
 //
 // This helps us guarantee that the value is only accessed
 // by one thread at a time.
-impl<T> Deref for Guard<'_, T> {
+impl<T, B, P> Deref for Guard<'_, T, B, P> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -61,7 +267,7 @@ impl<T> Deref for Guard<'_, T> {
     }
 }
 
-impl<T> DerefMut for Guard<'_, T> {
+impl<T, B, P> DerefMut for Guard<'_, T, B, P> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         // Safety: The very existence of this Guard
         // guarantees we've exclusively locked the spin lock
@@ -72,10 +278,259 @@ impl<T> DerefMut for Guard<'_, T> {
 // whenever the guard goes out of scope
 // The spin lock will be released and other threads
 // can get a hold on the value from now on.
-impl<T> Drop for Guard<'_, T> {
+//
+// Dropped by a panic that started while holding
+// the lock, the value might be left half-updated,
+// so that's when the lock gets poisoned.
+impl<T, B, P> Drop for Guard<'_, T, B, P> {
     fn drop(&mut self) {
-        self.lock
-            .locked
-            .store(false, std::sync::atomic::Ordering::Release)
+        self.lock.raw.release(self.poison_on_panic, &self.hold)
+    }
+}
+
+impl<'a, T, B, P> Guard<'a, T, B, P> {
+    /// Narrows the guard down to a part of the value,
+    /// e.g. a single field. The lock stays taken until
+    /// the MappedGuard is dropped.
+    ///
+    /// An associated function rather than a method,
+    /// so it doesn't get in the way of methods on `T`.
+    pub fn map<U: ?Sized>(guard: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedGuard<'a, U> {
+        // Safety: The guard gives us exclusive access, and it's only
+        // forgotten once f is done, so a panic in f still unlocks.
+        let value = NonNull::from(f(unsafe { &mut *guard.lock.value.get() }));
+        Self::into_mapped(guard, value)
+    }
+
+    /// Same as `map`, but gives the guard back
+    /// if `f` doesn't find anything to map to.
+    pub fn try_map<U: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedGuard<'a, U>, Self> {
+        // Safety: Same as in map
+        match f(unsafe { &mut *guard.lock.value.get() }) {
+            Some(value) => {
+                let value = NonNull::from(value);
+                Ok(Self::into_mapped(guard, value))
+            }
+            None => Err(guard),
+        }
+    }
+
+    fn into_mapped<U: ?Sized>(guard: Self, value: NonNull<U>) -> MappedGuard<'a, U> {
+        let lock = guard.lock;
+        let hold = guard.hold;
+        let poison_on_panic = guard.poison_on_panic;
+        // The MappedGuard is responsible for unlocking from now on
+        mem::forget(guard);
+        MappedGuard {
+            raw: &lock.raw,
+            hold,
+            poison_on_panic,
+            value,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B: Backoff, P: Poisoning> Guard<'_, T, B, P> {
+    /// Releases the lock while `f` runs and takes it back afterwards,
+    /// even if `f` panics. Other threads might have changed
+    /// the value in the meantime.
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> R {
+        struct RelockOnDrop<'g, 'a, T, B: Backoff, P: Poisoning>(&'g mut Guard<'a, T, B, P>);
+
+        impl<T, B: Backoff, P: Poisoning> Drop for RelockOnDrop<'_, '_, T, B, P> {
+            fn drop(&mut self) {
+                self.0.hold = self.0.lock.raw_lock();
+            }
+        }
+
+        // Not poisoning here, we're not panicking
+        guard.lock.raw.release(false, &guard.hold);
+        let _relock = RelockOnDrop(guard);
+        f()
+    }
+}
+
+/// A Guard narrowed down to a part of the locked value,
+/// see `Guard::map`.
+pub struct MappedGuard<'a, U: ?Sized> {
+    raw: &'a RawSpinLock,
+    hold: HoldTimer,
+    poison_on_panic: bool,
+    value: NonNull<U>,
+    // Behaves like the &mut U it actually is
+    _marker: PhantomData<&'a mut U>,
+}
+
+unsafe impl<U: ?Sized> Send for MappedGuard<'_, U> where U: Send {}
+unsafe impl<U: ?Sized> Sync for MappedGuard<'_, U> where U: Sync {}
+
+impl<U: ?Sized> Deref for MappedGuard<'_, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The lock is still taken
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<U: ?Sized> DerefMut for MappedGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The lock is still taken
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<U: ?Sized> Drop for MappedGuard<'_, U> {
+    fn drop(&mut self) {
+        self.raw.release(self.poison_on_panic, &self.hold)
+    }
+}
+
+impl<T, B: Backoff, P: Poisoning> Relock for Guard<'_, T, B, P> {
+    fn relock_after(self, f: impl FnOnce()) -> Self {
+        let lock = self.lock;
+        drop(self);
+        f();
+        P::into_guard(lock.lock())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{mem, thread, time::Duration};
+
+    use super::{Guard, SpinLock};
+    use crate::locks::backoff::{Backoff, Exponential, SpinThenYield, TestAndTestAndSet};
+
+    #[test]
+    fn test_try_lock() {
+        let lock = SpinLock::new(1);
+        let guard = lock.try_lock().unwrap();
+        assert!(lock.is_locked());
+        assert!(lock.try_lock().is_none());
+        assert!(lock.try_lock_for(Duration::from_millis(10)).is_none());
+        drop(guard);
+        assert!(!lock.is_locked());
+        assert!(lock.try_lock_for(Duration::from_millis(10)).is_some());
+    }
+
+    #[test]
+    fn test_backoff_strategies() {
+        fn count_to(lock: &SpinLock<usize, impl Backoff>) -> usize {
+            thread::scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        for _ in 0..1000 {
+                            *lock.lock() += 1;
+                        }
+                    });
+                }
+            });
+            *lock.lock()
+        }
+
+        assert_eq!(count_to(&SpinLock::new(0)), 4000);
+        assert_eq!(count_to(&SpinLock::<_, Exponential>::with_backoff(0)), 4000);
+        assert_eq!(
+            count_to(&SpinLock::<_, SpinThenYield>::with_backoff(0)),
+            4000
+        );
+        assert_eq!(
+            count_to(&SpinLock::<_, TestAndTestAndSet>::with_backoff(0)),
+            4000
+        );
+    }
+
+    #[test]
+    fn test_poisoning() {
+        let lock = SpinLock::poisoning(0);
+
+        thread::scope(|s| {
+            let result = s
+                .spawn(|| {
+                    let mut guard = lock.lock().unwrap();
+                    *guard = 1;
+                    panic!("half-way through an update");
+                })
+                .join();
+            assert!(result.is_err());
+        });
+
+        assert!(lock.is_poisoned());
+        let mut guard = match lock.lock() {
+            Ok(_) => panic!("lock should be poisoned"),
+            Err(e) => e.into_inner(),
+        };
+        assert_eq!(*guard, 1);
+        *guard = 2;
+        drop(guard);
+
+        lock.clear_poison();
+        assert_eq!(*lock.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_no_poisoning_by_default() {
+        let lock = SpinLock::new(0);
+        thread::scope(|s| {
+            let result = s
+                .spawn(|| {
+                    let _guard = lock.lock();
+                    panic!("nobody will notice");
+                })
+                .join();
+            assert!(result.is_err());
+        });
+        assert_eq!(*lock.lock(), 0);
+    }
+
+    #[test]
+    fn test_mapped_guard() {
+        let lock = SpinLock::new((1, vec![1, 2, 3]));
+
+        let mut list = Guard::map(lock.lock(), |(_, list)| list);
+        list.push(4);
+        assert!(lock.is_locked());
+        drop(list);
+        assert!(!lock.is_locked());
+
+        let first = Guard::try_map(lock.lock(), |(_, list)| list.first_mut());
+        *first.ok().unwrap() = 0;
+
+        let guard = Guard::try_map(lock.lock(), |(_, list)| list.get_mut(10));
+        assert!(guard.is_err());
+        drop(guard);
+
+        assert_eq!(*lock.lock(), (1, vec![0, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_unlocked() {
+        let lock = SpinLock::new(0);
+        let mut guard = lock.lock();
+        *guard += 1;
+        Guard::unlocked(&mut guard, || {
+            assert!(!lock.is_locked());
+            *lock.lock() += 1;
+        });
+        assert!(lock.is_locked());
+        *guard += 1;
+        drop(guard);
+        assert_eq!(*lock.lock(), 3);
+    }
+
+    #[test]
+    fn test_force_unlock() {
+        let mut lock = SpinLock::new(1);
+        mem::forget(lock.lock());
+        assert!(lock.is_locked());
+        // Safety: the only guard was forgotten
+        unsafe { lock.force_unlock() };
+        *lock.get_mut() += 1;
+        assert_eq!(lock.into_inner(), 2);
     }
 }
@@ -0,0 +1,221 @@
+//! Contention statistics, enabled with the `lock-stats` cargo feature.
+//!
+//! Every lock keeps a `Recorder` that gets told when somebody starts
+//! waiting, how often they had to spin or sleep, when they got the lock
+//! and when they let go of it. Without the feature, the recorder and
+//! its timers are zero-sized and all of this compiles down to nothing.
+
+#[cfg(feature = "lock-stats")]
+use std::{
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+
+/// A snapshot of a lock's statistics, see the `stats()`
+/// method on the lock types.
+#[cfg(feature = "lock-stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// Number of times the lock was taken.
+    pub acquisitions: u64,
+    /// How many of those didn't get the lock on the first try.
+    pub contended: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+    pub total_hold: Duration,
+    pub max_hold: Duration,
+    /// Failed attempts at taking the lock, counting
+    /// both spin loop iterations and sleeps.
+    pub spins: u64,
+}
+
+pub(crate) struct Recorder {
+    #[cfg(feature = "lock-stats")]
+    acquisitions: AtomicU64,
+    #[cfg(feature = "lock-stats")]
+    contended: AtomicU64,
+    #[cfg(feature = "lock-stats")]
+    total_wait_ns: AtomicU64,
+    #[cfg(feature = "lock-stats")]
+    max_wait_ns: AtomicU64,
+    #[cfg(feature = "lock-stats")]
+    total_hold_ns: AtomicU64,
+    #[cfg(feature = "lock-stats")]
+    max_hold_ns: AtomicU64,
+    #[cfg(feature = "lock-stats")]
+    spins: AtomicU64,
+}
+
+/// Started right before trying to take a lock.
+pub(crate) struct WaitTimer {
+    #[cfg(feature = "lock-stats")]
+    start: Instant,
+    #[cfg(feature = "lock-stats")]
+    spins: u64,
+}
+
+/// Kept in a guard, started once the lock was taken.
+#[derive(Clone, Copy)]
+pub(crate) struct HoldTimer {
+    #[cfg(feature = "lock-stats")]
+    start: Instant,
+}
+
+impl Recorder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            #[cfg(feature = "lock-stats")]
+            acquisitions: AtomicU64::new(0),
+            #[cfg(feature = "lock-stats")]
+            contended: AtomicU64::new(0),
+            #[cfg(feature = "lock-stats")]
+            total_wait_ns: AtomicU64::new(0),
+            #[cfg(feature = "lock-stats")]
+            max_wait_ns: AtomicU64::new(0),
+            #[cfg(feature = "lock-stats")]
+            total_hold_ns: AtomicU64::new(0),
+            #[cfg(feature = "lock-stats")]
+            max_hold_ns: AtomicU64::new(0),
+            #[cfg(feature = "lock-stats")]
+            spins: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn start_wait(&self) -> WaitTimer {
+        WaitTimer {
+            #[cfg(feature = "lock-stats")]
+            start: Instant::now(),
+            #[cfg(feature = "lock-stats")]
+            spins: 0,
+        }
+    }
+
+    pub(crate) fn acquired(&self, _wait: WaitTimer) -> HoldTimer {
+        #[cfg(feature = "lock-stats")]
+        {
+            let now = Instant::now();
+            let waited = nanos(now - _wait.start);
+            self.acquisitions.fetch_add(1, Relaxed);
+            if _wait.spins > 0 {
+                self.contended.fetch_add(1, Relaxed);
+                self.spins.fetch_add(_wait.spins, Relaxed);
+            }
+            self.total_wait_ns.fetch_add(waited, Relaxed);
+            self.max_wait_ns.fetch_max(waited, Relaxed);
+            HoldTimer { start: now }
+        }
+        #[cfg(not(feature = "lock-stats"))]
+        HoldTimer {}
+    }
+
+    pub(crate) fn released(&self, _hold: &HoldTimer) {
+        #[cfg(feature = "lock-stats")]
+        {
+            let held = nanos(_hold.start.elapsed());
+            self.total_hold_ns.fetch_add(held, Relaxed);
+            self.max_hold_ns.fetch_max(held, Relaxed);
+        }
+    }
+
+    #[cfg(feature = "lock-stats")]
+    pub(crate) fn snapshot(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Relaxed),
+            contended: self.contended.load(Relaxed),
+            total_wait: Duration::from_nanos(self.total_wait_ns.load(Relaxed)),
+            max_wait: Duration::from_nanos(self.max_wait_ns.load(Relaxed)),
+            total_hold: Duration::from_nanos(self.total_hold_ns.load(Relaxed)),
+            max_hold: Duration::from_nanos(self.max_hold_ns.load(Relaxed)),
+            spins: self.spins.load(Relaxed),
+        }
+    }
+}
+
+impl WaitTimer {
+    /// Called for every failed attempt, spin or sleep.
+    pub(crate) fn spin(&mut self) {
+        #[cfg(feature = "lock-stats")]
+        {
+            self.spins += 1;
+        }
+    }
+}
+
+#[cfg(feature = "lock-stats")]
+fn nanos(d: Duration) -> u64 {
+    d.as_nanos().min(u64::MAX as u128) as u64
+}
+
+#[cfg(all(test, feature = "lock-stats"))]
+mod test {
+    use std::{thread, time::Duration};
+
+    use crate::locks::{
+        mcs::McsLock, mutex::Mutex, reentrant::ReentrantLock, rwlock::RwLock, spin_lock::SpinLock,
+        ticket_lock::TicketLock,
+    };
+
+    #[test]
+    fn test_spin_lock_stats() {
+        let lock = SpinLock::new(0);
+
+        let guard = lock.lock();
+        thread::scope(|s| {
+            s.spawn(|| *lock.lock() += 1);
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+
+        let stats = lock.stats();
+        assert_eq!(stats.acquisitions, 2);
+        assert_eq!(stats.contended, 1);
+        assert!(stats.spins > 0);
+        assert!(stats.max_wait >= Duration::from_millis(10));
+        assert!(stats.max_hold >= Duration::from_millis(10));
+        assert!(stats.total_hold >= stats.max_hold);
+    }
+
+    #[test]
+    fn test_spin_lock_try_lock_for_stats() {
+        let lock = SpinLock::new(0);
+
+        let guard = lock.lock();
+        thread::scope(|s| {
+            s.spawn(|| *lock.try_lock_for(Duration::from_secs(10)).unwrap() += 1);
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+
+        let stats = lock.stats();
+        assert_eq!(stats.acquisitions, 2);
+        assert_eq!(stats.contended, 1);
+        assert!(stats.spins > 0);
+        assert!(stats.max_wait >= Duration::from_millis(10));
+        assert!(stats.max_wait < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_all_locks_count_acquisitions() {
+        let mutex = Mutex::new(());
+        let rwlock = RwLock::new(());
+        let ticket_lock = TicketLock::new(());
+        let mcs_lock = McsLock::new(());
+        let reentrant_lock = ReentrantLock::new(());
+
+        for _ in 0..3 {
+            drop(mutex.lock());
+            drop(rwlock.read());
+            drop(rwlock.write());
+            drop(ticket_lock.lock());
+            drop(mcs_lock.lock());
+            drop(reentrant_lock.lock());
+        }
+
+        assert_eq!(mutex.stats().acquisitions, 3);
+        assert_eq!(rwlock.stats().acquisitions, 6);
+        assert_eq!(ticket_lock.stats().acquisitions, 3);
+        assert_eq!(mcs_lock.stats().acquisitions, 3);
+        assert_eq!(reentrant_lock.stats().acquisitions, 3);
+        assert_eq!(mutex.stats().contended, 0);
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering::*},
+};
+
+use super::stats::{HoldTimer, Recorder};
+
+/// A fair spin lock: every thread draws a ticket
+/// and waits until its number is being served,
+/// so the lock is handed out in FIFO order.
+pub struct TicketLock<T> {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    stats: Recorder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for TicketLock<T> where T: Send {}
+
+impl<T> TicketLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            stats: Recorder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        let mut wait = self.stats.start_wait();
+        // Counters wrap around, which is fine as long as
+        // there are fewer than 2^32 threads waiting at once.
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        while self.now_serving.load(Acquire) != ticket {
+            wait.spin();
+            spin_loop();
+        }
+        Guard {
+            lock: self,
+            hold: self.stats.acquired(wait),
+        }
+    }
+
+    /// Takes the lock only if nobody holds it or waits for it.
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        let wait = self.stats.start_wait();
+        // Acquire, since the previous holder released
+        // the lock through now_serving, not next_ticket.
+        let ticket = self.now_serving.load(Acquire);
+        self.next_ticket
+            .compare_exchange(ticket, ticket.wrapping_add(1), Acquire, Relaxed)
+            .ok()
+            .map(|_| Guard {
+                lock: self,
+                hold: self.stats.acquired(wait),
+            })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.next_ticket.load(Relaxed) != self.now_serving.load(Relaxed)
+    }
+
+    /// Number of threads waiting for the lock, not counting
+    /// the one holding it. Only a snapshot, it might have
+    /// changed by the time you look at it.
+    pub fn queue_len(&self) -> u32 {
+        let now_serving = self.now_serving.load(Relaxed);
+        let next_ticket = self.next_ticket.load(Relaxed);
+        next_ticket.wrapping_sub(now_serving).saturating_sub(1)
+    }
+
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> super::stats::LockStats {
+        self.stats.snapshot()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+pub struct Guard<'a, T> {
+    lock: &'a TicketLock<T>,
+    hold: HoldTimer,
+}
+
+unsafe impl<T> Sync for Guard<'_, T> where T: Sync {}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees our ticket is being served
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The very existence of this Guard
+        // guarantees our ticket is being served
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+// Serving the next ticket hands the lock
+// to whoever has been waiting the longest.
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.stats.released(&self.hold);
+        self.lock.now_serving.fetch_add(1, Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{hint::spin_loop, thread};
+
+    use super::TicketLock;
+
+    #[test]
+    fn test_ticket_lock_fifo() {
+        let lock = TicketLock::new(Vec::new());
+
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_none());
+
+        thread::scope(|s| {
+            // Let the threads queue up one after another
+            for i in 0..3 {
+                let lock = &lock;
+                s.spawn(move || lock.lock().push(i));
+                while lock.queue_len() != i + 1 {
+                    spin_loop();
+                }
+            }
+            drop(guard);
+        });
+
+        assert_eq!(*lock.lock(), [0, 1, 2]);
+        assert_eq!(lock.queue_len(), 0);
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn test_lock_and_try_lock_hand_off() {
+        let lock = TicketLock::new(Vec::new());
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..100 {
+                    lock.lock().push(i);
+                }
+            });
+            let mut taken = 0;
+            while taken < 100 {
+                if let Some(mut v) = lock.try_lock() {
+                    // Whatever the other thread pushed must be visible
+                    taken += v.drain(..).count();
+                }
+                spin_loop();
+            }
+        });
+        assert!(lock.into_inner().is_empty());
+    }
+}
@@ -5,6 +5,7 @@ use atomics_and_locks::{
         one_shot_channel::OneShotChannel, sender_receiver::channel, sender_receiver_borrow,
     },
     locks::spin_lock::SpinLock,
+    sync::event::ManualResetEvent,
 };
 
 fn main() {
@@ -16,20 +17,19 @@ fn main() {
 
 fn one_shot_channel() {
     let channel = OneShotChannel::new();
-    let t = thread::current();
+    let ready = ManualResetEvent::new(false);
 
     thread::scope(|s| {
         s.spawn(|| {
             channel.send("Hi there!");
-            t.unpark();
+            ready.set();
         });
 
-        while !channel.is_ready() {
-            println!("Parking main thread...");
-            thread::park();
-        }
+        println!("Waiting for the message...");
+        ready.wait();
+        assert!(channel.is_ready());
 
-        println!("Unparking...");
+        println!("Message is ready");
 
         assert_eq!(channel.receive(), "Hi there!");
 
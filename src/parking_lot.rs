@@ -0,0 +1,372 @@
+//! A global table of wait queues, keyed by address, like the one
+//! in the parking_lot crate.
+//!
+//! A lock only needs a couple of bits to remember whether
+//! anybody is parked on it, instead of a futex word of its own
+//! or a list of thread handles. The queues live in a fixed number
+//! of buckets, each protected by a Mutex, so addresses that
+//! hash to the same bucket share that Mutex (but not their queue).
+//!
+//! The callbacks run while the bucket is locked, which is what makes
+//! checking the lock's state and (un)parking atomic. They must not
+//! park or unpark anything themselves.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU32, Ordering::*},
+        Arc,
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    atomic_wait::{wait, wait_timeout, wake_one},
+    locks::mutex::Mutex,
+};
+
+const NUM_BUCKETS: usize = 64;
+
+static BUCKETS: [Bucket; NUM_BUCKETS] = [const { Bucket::new() }; NUM_BUCKETS];
+
+struct Bucket {
+    queue: Mutex<VecDeque<Arc<Waiter>>>,
+}
+
+impl Bucket {
+    const fn new() -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+/// A parked thread.
+struct Waiter {
+    addr: usize,
+    thread: ThreadId,
+    /// 1 once unparked, the thread sleeps on this.
+    unparked: AtomicU32,
+}
+
+impl Waiter {
+    fn unpark(&self) {
+        self.unparked.store(1, Release);
+        wake_one(&self.unparked);
+    }
+}
+
+fn bucket(addr: usize) -> &'static Bucket {
+    // Fibonacci hashing, using the top bits of the product
+    let hash = (addr as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 58;
+    &BUCKETS[hash as usize % NUM_BUCKETS]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParkResult {
+    /// Woken up by one of the unpark functions.
+    Unparked,
+    /// `validate` returned false, so we didn't park at all.
+    Invalid,
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnparkResult {
+    pub unparked_threads: usize,
+    /// Whether there are still threads parked on the address.
+    pub have_more_threads: bool,
+}
+
+/// What `unpark_filter` does with a parked thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Unpark,
+    Skip,
+    /// Leaves this and all remaining threads parked.
+    Stop,
+}
+
+/// Parks the current thread in the queue for `addr`, but only if
+/// `validate` returns true. `validate` runs with the queue locked,
+/// so no unpark for `addr` can happen between it and parking.
+///
+/// `before_sleep` runs after the queue was unlocked,
+/// right before going to sleep.
+pub fn park(
+    addr: usize,
+    validate: impl FnOnce() -> bool,
+    before_sleep: impl FnOnce(),
+    timeout: Option<Duration>,
+) -> ParkResult {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let bucket = bucket(addr);
+
+    let waiter = {
+        let mut queue = bucket.queue.lock();
+        if !validate() {
+            return ParkResult::Invalid;
+        }
+        let waiter = Arc::new(Waiter {
+            addr,
+            thread: thread::current().id(),
+            unparked: AtomicU32::new(0),
+        });
+        queue.push_back(waiter.clone());
+        waiter
+    };
+
+    before_sleep();
+
+    while waiter.unparked.load(Acquire) == 0 {
+        let Some(deadline) = deadline else {
+            wait(&waiter.unparked, 0);
+            continue;
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            let mut queue = bucket.queue.lock();
+            // We might have been unparked right before taking the lock
+            if waiter.unparked.load(Acquire) == 1 {
+                break;
+            }
+            queue.retain(|w| !Arc::ptr_eq(w, &waiter));
+            return ParkResult::TimedOut;
+        }
+        wait_timeout(&waiter.unparked, 0, Some(remaining));
+    }
+    ParkResult::Unparked
+}
+
+/// Unparks the thread that's been parked on `addr` the longest.
+///
+/// `callback` runs before it's woken up, with the queue still locked,
+/// so it can update the lock's state before any other thread parks.
+pub fn unpark_one(addr: usize, callback: impl FnOnce(UnparkResult)) -> UnparkResult {
+    let mut first = true;
+    unpark_filter(
+        addr,
+        |_| {
+            if first {
+                first = false;
+                FilterOp::Unpark
+            } else {
+                FilterOp::Stop
+            }
+        },
+        callback,
+    )
+}
+
+/// Unparks all threads parked on `addr`, returning how many there were.
+pub fn unpark_all(addr: usize) -> usize {
+    unpark_filter(addr, |_| FilterOp::Unpark, |_| {}).unparked_threads
+}
+
+/// Goes through the threads parked on `addr`, oldest first,
+/// and lets `filter` decide which ones to unpark.
+pub fn unpark_filter(
+    addr: usize,
+    mut filter: impl FnMut(ThreadId) -> FilterOp,
+    callback: impl FnOnce(UnparkResult),
+) -> UnparkResult {
+    let bucket = bucket(addr);
+    let mut queue = bucket.queue.lock();
+
+    let mut result = UnparkResult::default();
+    let mut unparked = Vec::new();
+    let mut stopped = false;
+    queue.retain(|w| {
+        if w.addr != addr {
+            return true;
+        }
+        if stopped {
+            result.have_more_threads = true;
+            return true;
+        }
+        match filter(w.thread) {
+            FilterOp::Unpark => {
+                unparked.push(w.clone());
+                false
+            }
+            FilterOp::Skip => {
+                result.have_more_threads = true;
+                true
+            }
+            FilterOp::Stop => {
+                stopped = true;
+                result.have_more_threads = true;
+                true
+            }
+        }
+    });
+    result.unparked_threads = unparked.len();
+
+    callback(result);
+    drop(queue);
+
+    // Waking them up after unlocking the queue,
+    // so they don't immediately block on it.
+    for waiter in unparked {
+        waiter.unpark();
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::UnsafeCell,
+        sync::atomic::{AtomicU8, AtomicUsize, Ordering::*},
+        thread,
+        time::Duration,
+    };
+
+    use super::{park, unpark_all, unpark_filter, unpark_one, FilterOp, ParkResult};
+
+    const LOCKED: u8 = 1;
+    const PARKED: u8 = 2;
+
+    /// A lock that's a single byte, but still puts threads to sleep.
+    struct ByteLock {
+        state: AtomicU8,
+    }
+
+    impl ByteLock {
+        fn lock(&self) {
+            let addr = &self.state as *const _ as usize;
+            loop {
+                let s = self.state.load(Relaxed);
+                if s & LOCKED == 0 {
+                    if self
+                        .state
+                        .compare_exchange_weak(s, s | LOCKED, Acquire, Relaxed)
+                        .is_ok()
+                    {
+                        return;
+                    }
+                    continue;
+                }
+                if s & PARKED == 0
+                    && self
+                        .state
+                        .compare_exchange_weak(s, s | PARKED, Relaxed, Relaxed)
+                        .is_err()
+                {
+                    continue;
+                }
+                park(
+                    addr,
+                    || self.state.load(Relaxed) == LOCKED | PARKED,
+                    || {},
+                    None,
+                );
+            }
+        }
+
+        fn unlock(&self) {
+            if self
+                .state
+                .compare_exchange(LOCKED, 0, Release, Relaxed)
+                .is_ok()
+            {
+                return;
+            }
+            let addr = &self.state as *const _ as usize;
+            unpark_one(addr, |result| {
+                // Still holding the queue, so nobody can park
+                // between us looking and clearing the bit
+                let parked = if result.have_more_threads { PARKED } else { 0 };
+                self.state.store(parked, Release);
+            });
+        }
+    }
+
+    #[test]
+    fn test_byte_lock() {
+        struct Counter(UnsafeCell<u32>);
+        unsafe impl Sync for Counter {}
+        impl Counter {
+            fn get(&self) -> *mut u32 {
+                self.0.get()
+            }
+        }
+
+        let lock = ByteLock {
+            state: AtomicU8::new(0),
+        };
+        let counter = Counter(UnsafeCell::new(0));
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        lock.lock();
+                        // Safety: We're holding the lock
+                        unsafe { *counter.get() += 1 };
+                        lock.unlock();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(counter.0.into_inner(), 4000);
+        assert_eq!(lock.state.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn test_park_invalid_and_timeout() {
+        let addr = 1;
+        assert_eq!(park(addr, || false, || {}, None), ParkResult::Invalid);
+        assert_eq!(
+            park(addr, || true, || {}, Some(Duration::from_millis(10))),
+            ParkResult::TimedOut
+        );
+        // Timing out took it out of the queue
+        assert_eq!(unpark_all(addr), 0);
+    }
+
+    #[test]
+    fn test_unpark_filter() {
+        let addr = 2;
+        let parked = AtomicUsize::new(0);
+        let before_sleep = || {
+            parked.fetch_add(1, Relaxed);
+        };
+
+        thread::scope(|s| {
+            let t1 = s.spawn(|| park(addr, || true, before_sleep, None));
+            while parked.load(Relaxed) < 1 {
+                thread::yield_now();
+            }
+            let t2 = s.spawn(|| park(addr, || true, before_sleep, None));
+            while parked.load(Relaxed) < 2 {
+                thread::yield_now();
+            }
+
+            // Only the second one
+            let t2_id = t2.thread().id();
+            let result = unpark_filter(
+                addr,
+                |thread| {
+                    if thread == t2_id {
+                        FilterOp::Unpark
+                    } else {
+                        FilterOp::Skip
+                    }
+                },
+                |_| {},
+            );
+            assert_eq!(result.unparked_threads, 1);
+            assert!(result.have_more_threads);
+            assert_eq!(t2.join().unwrap(), ParkResult::Unparked);
+            assert!(!t1.is_finished());
+
+            let result = unpark_one(addr, |_| {});
+            assert_eq!(result.unparked_threads, 1);
+            assert!(!result.have_more_threads);
+            assert_eq!(t1.join().unwrap(), ParkResult::Unparked);
+        });
+    }
+}
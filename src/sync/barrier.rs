@@ -0,0 +1,197 @@
+use std::{
+    error::Error,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::locks::{condvar::Condvar, mutex::Mutex};
+
+/// Blocks threads until `n` of them have called `wait`,
+/// then lets them all go at once.
+///
+/// A barrier can be reused: once everybody has been let go,
+/// the next `n` calls to `wait` make up the next round.
+/// Rounds are numbered from 0, and exactly one thread
+/// of every round is told it's the leader.
+pub struct Barrier {
+    n: usize,
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+struct State {
+    /// Threads waiting in the current round.
+    arrived: usize,
+    /// Number of the current round.
+    round: u64,
+}
+
+/// Returned by `Barrier::wait` to all threads of a round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult {
+    is_leader: bool,
+    round: u64,
+}
+
+impl BarrierWaitResult {
+    /// True for exactly one thread per round: the last one to arrive.
+    pub fn is_leader(&self) -> bool {
+        self.is_leader
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+}
+
+/// Returned by `Barrier::wait_timeout` when not enough threads
+/// arrived in time. The thread doesn't count as arrived anymore,
+/// so the round still needs `n` threads to complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierTimeout {
+    round: u64,
+}
+
+impl BarrierTimeout {
+    /// The round that timed out.
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+}
+
+impl fmt::Display for BarrierTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out waiting on barrier in round {}", self.round)
+    }
+}
+
+impl Error for BarrierTimeout {}
+
+impl Barrier {
+    /// A barrier for `n` threads. Like std's Barrier,
+    /// a barrier for 0 threads behaves like one for 1.
+    pub const fn new(n: usize) -> Self {
+        Self {
+            n,
+            state: Mutex::new(State {
+                arrived: 0,
+                round: 0,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn wait(&self) -> BarrierWaitResult {
+        let mut state = self.state.lock();
+        let round = state.round;
+        if let Some(result) = self.arrive(&mut state) {
+            return result;
+        }
+        let _state = self.condvar.wait_while(state, |s| s.round == round);
+        BarrierWaitResult {
+            is_leader: false,
+            round,
+        }
+    }
+
+    /// Like `wait`, but gives up if the round
+    /// isn't complete within `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<BarrierWaitResult, BarrierTimeout> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock();
+        let round = state.round;
+        if let Some(result) = self.arrive(&mut state) {
+            return Ok(result);
+        }
+        while state.round == round {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let (s, result) = self.condvar.wait_timeout(state, remaining);
+            state = s;
+            // The round might have completed right at the deadline
+            if result.timed_out() && state.round == round {
+                state.arrived -= 1;
+                return Err(BarrierTimeout { round });
+            }
+        }
+        Ok(BarrierWaitResult {
+            is_leader: false,
+            round,
+        })
+    }
+
+    /// Counts the calling thread in. If it's the last one,
+    /// starts the next round and wakes everybody up.
+    fn arrive(&self, state: &mut State) -> Option<BarrierWaitResult> {
+        state.arrived += 1;
+        if state.arrived < self.n {
+            return None;
+        }
+        let round = state.round;
+        state.arrived = 0;
+        state.round += 1;
+        self.condvar.notify_all();
+        Some(BarrierWaitResult {
+            is_leader: true,
+            round,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::Duration,
+    };
+
+    use super::Barrier;
+
+    #[test]
+    fn test_barrier_rounds() {
+        let barrier = Barrier::new(4);
+        let arrived = AtomicUsize::new(0);
+        let leaders = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for round in 0..10 {
+                        arrived.fetch_add(1, Relaxed);
+                        let result = barrier.wait();
+                        assert_eq!(result.round(), round);
+                        // Everybody arrived before anyone was let go
+                        assert!(arrived.load(Relaxed) >= 4 * (round as usize + 1));
+                        if result.is_leader() {
+                            leaders.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(leaders.load(Relaxed), 10);
+    }
+
+    #[test]
+    fn test_barrier_wait_timeout() {
+        let barrier = Barrier::new(2);
+
+        let timeout = barrier.wait_timeout(Duration::from_millis(10)).unwrap_err();
+        assert_eq!(timeout.round(), 0);
+
+        // The timed out thread doesn't count, so the
+        // round still needs two threads to complete
+        thread::scope(|s| {
+            let t = s.spawn(|| barrier.wait_timeout(Duration::from_secs(10)));
+            let result = barrier.wait();
+            let other = t.join().unwrap().unwrap();
+            assert_eq!(result.round(), 0);
+            assert_eq!(other.round(), 0);
+            assert!(result.is_leader() != other.is_leader());
+        });
+
+        let timeout = barrier.wait_timeout(Duration::from_millis(10)).unwrap_err();
+        assert_eq!(timeout.round(), 1);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering::*},
+    time::{Duration, Instant},
+};
+
+use crate::atomic_wait::{wait_timeout, wake_all, wake_one};
+
+const UNSET: u32 = 0;
+const SET: u32 = 1;
+const UNSET_WAITERS: u32 = 2;
+
+/// A flag threads can wait on. Once set, it lets all
+/// waiters through until it's `reset` again.
+pub struct ManualResetEvent {
+    /// 0: not set
+    /// 1: set
+    /// 2: not set, threads (might be) waiting
+    state: AtomicU32,
+}
+
+impl ManualResetEvent {
+    pub const fn new(set: bool) -> Self {
+        Self {
+            state: AtomicU32::new(if set { SET } else { UNSET }),
+        }
+    }
+
+    pub fn set(&self) {
+        // Only wake threads up if somebody might be waiting
+        if self.state.swap(SET, Release) == UNSET_WAITERS {
+            wake_all(&self.state);
+        }
+    }
+
+    pub fn reset(&self) {
+        let _ = self.state.compare_exchange(SET, UNSET, Relaxed, Relaxed);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.state.load(Acquire) == SET
+    }
+
+    /// Blocks until the event is set.
+    pub fn wait(&self) {
+        self.wait_until(None);
+    }
+
+    /// Returns false if the event wasn't set within `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.wait_until(Some(Instant::now() + timeout))
+    }
+
+    fn wait_until(&self, deadline: Option<Instant>) -> bool {
+        loop {
+            let s = self.state.load(Acquire);
+            if s == SET {
+                return true;
+            }
+            if s == UNSET
+                && self
+                    .state
+                    .compare_exchange(UNSET, UNSET_WAITERS, Relaxed, Relaxed)
+                    .is_err()
+            {
+                continue;
+            }
+            let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            if timeout.is_some_and(|t| t.is_zero()) {
+                return false;
+            }
+            wait_timeout(&self.state, UNSET_WAITERS, timeout);
+        }
+    }
+}
+
+impl Default for ManualResetEvent {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+/// A flag that lets exactly one waiter through per `set`,
+/// resetting itself as that waiter goes. Setting it while
+/// nobody waits lets the next waiter through right away,
+/// but setting it again before that has no effect.
+pub struct AutoResetEvent {
+    /// 0: not set
+    /// 1: set
+    state: AtomicU32,
+    /// Number of sleeping threads, so setting the
+    /// event only needs a syscall when somebody sleeps.
+    waiters: AtomicU32,
+}
+
+impl AutoResetEvent {
+    pub const fn new(set: bool) -> Self {
+        Self {
+            state: AtomicU32::new(if set { SET } else { UNSET }),
+            waiters: AtomicU32::new(0),
+        }
+    }
+
+    pub fn set(&self) {
+        // SeqCst, paired with the one in wait_until: either the
+        // waiter sees the event is set, or we see it waiting.
+        if self.state.swap(SET, SeqCst) == UNSET && self.waiters.load(SeqCst) > 0 {
+            wake_one(&self.state);
+        }
+    }
+
+    pub fn reset(&self) {
+        self.state.store(UNSET, Relaxed);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.state.load(Relaxed) == SET
+    }
+
+    /// Blocks until the event is set, and resets it.
+    pub fn wait(&self) {
+        self.wait_until(None);
+    }
+
+    /// Returns false if the event wasn't set within `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.wait_until(Some(Instant::now() + timeout))
+    }
+
+    fn wait_until(&self, deadline: Option<Instant>) -> bool {
+        loop {
+            // A woken thread might lose this race against a newly
+            // arriving one, but the set still lets only one of them go.
+            if self
+                .state
+                .compare_exchange(SET, UNSET, Acquire, Relaxed)
+                .is_ok()
+            {
+                return true;
+            }
+            let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            if timeout.is_some_and(|t| t.is_zero()) {
+                return false;
+            }
+            self.waiters.fetch_add(1, SeqCst);
+            wait_timeout(&self.state, UNSET, timeout);
+            self.waiters.fetch_sub(1, Relaxed);
+        }
+    }
+}
+
+impl Default for AutoResetEvent {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::Duration,
+    };
+
+    use super::{AutoResetEvent, ManualResetEvent};
+
+    #[test]
+    fn test_manual_reset_event() {
+        let event = ManualResetEvent::new(false);
+        assert!(!event.wait_timeout(Duration::from_millis(10)));
+
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| event.wait());
+            }
+            thread::sleep(Duration::from_millis(10));
+            event.set();
+        });
+
+        // Stays set until reset
+        assert!(event.is_set());
+        event.wait();
+        event.reset();
+        assert!(!event.is_set());
+        assert!(!event.wait_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_auto_reset_event() {
+        let event = AutoResetEvent::new(false);
+        let woken = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    event.wait();
+                    woken.fetch_add(1, Relaxed);
+                });
+            }
+            for i in 1..=3 {
+                event.set();
+                // Exactly one waiter per set
+                while woken.load(Relaxed) < i {
+                    thread::yield_now();
+                }
+                thread::sleep(Duration::from_millis(10));
+                assert_eq!(woken.load(Relaxed), i);
+            }
+        });
+
+        assert!(!event.is_set());
+        event.set();
+        assert!(event.wait_timeout(Duration::from_millis(10)));
+        assert!(!event.wait_timeout(Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering::*},
+    time::{Duration, Instant},
+};
+
+use crate::atomic_wait::{wait_timeout, wake_all};
+
+/// Lets threads wait until a counter has been counted down to zero,
+/// e.g. until N workers are done, without having to join them.
+///
+/// Unlike a Barrier, the threads counting down don't wait
+/// for each other, and a latch can't be reused.
+pub struct CountDownLatch {
+    count: AtomicU32,
+}
+
+impl CountDownLatch {
+    pub const fn new(count: u32) -> Self {
+        Self {
+            count: AtomicU32::new(count),
+        }
+    }
+
+    /// Counting down an open latch does nothing.
+    pub fn count_down(&self) {
+        // Release, so whatever the counting thread did
+        // is visible to the threads that were waiting.
+        if self
+            .count
+            .fetch_update(Release, Relaxed, |c| c.checked_sub(1))
+            == Ok(1)
+        {
+            wake_all(&self.count);
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count.load(Relaxed)
+    }
+
+    /// Blocks until the count reaches zero.
+    pub fn wait(&self) {
+        wait_until_zero(&self.count, None);
+    }
+
+    /// Returns false if the count didn't reach zero within `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        wait_until_zero(&self.count, Some(Instant::now() + timeout))
+    }
+}
+
+/// Sleeps until `count` is zero. Whoever brings it
+/// down to zero has to wake everybody up.
+pub(super) fn wait_until_zero(count: &AtomicU32, deadline: Option<Instant>) -> bool {
+    loop {
+        let c = count.load(Acquire);
+        if c == 0 {
+            return true;
+        }
+        let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        if timeout.is_some_and(|t| t.is_zero()) {
+            return false;
+        }
+        // Counting down from c to something other than zero doesn't
+        // wake us up, but then the kernel sees c changed and
+        // we won't go to sleep in the first place.
+        wait_timeout(count, c, timeout);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::Duration,
+    };
+
+    use super::CountDownLatch;
+
+    #[test]
+    fn test_latch() {
+        let latch = CountDownLatch::new(3);
+        let done = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    thread::sleep(Duration::from_millis(10));
+                    done.fetch_add(1, Relaxed);
+                    latch.count_down();
+                    // Not waiting for the others
+                });
+            }
+            latch.wait();
+            assert_eq!(done.load(Relaxed), 3);
+        });
+
+        latch.count_down();
+        assert_eq!(latch.count(), 0);
+    }
+
+    #[test]
+    fn test_latch_wait_timeout() {
+        let latch = CountDownLatch::new(1);
+        assert!(!latch.wait_timeout(Duration::from_millis(10)));
+        latch.count_down();
+        assert!(latch.wait_timeout(Duration::from_millis(10)));
+    }
+}
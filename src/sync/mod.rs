@@ -0,0 +1,6 @@
+pub mod barrier;
+pub mod event;
+pub mod latch;
+pub mod once;
+pub mod phaser;
+pub mod wait_group;
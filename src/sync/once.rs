@@ -0,0 +1,353 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::atomic::{AtomicU32, Ordering::*},
+};
+
+use crate::atomic_wait::{wait, wake_all};
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+const RUNNING_WAITERS: u32 = 2;
+const COMPLETE: u32 = 3;
+const POISONED: u32 = 4;
+
+/// Runs a piece of code exactly once, even when called
+/// from several threads at the same time.
+///
+/// Threads arriving while another one is running the
+/// closure go to sleep until it's done. If the closure
+/// panics, the Once is poisoned: `call_once` panics from
+/// then on, but `call_once_force` can still run it again.
+pub struct Once {
+    /// 0: not run yet
+    /// 1: running, no other threads waiting
+    /// 2: running, other threads (might be) waiting
+    /// 3: done
+    /// 4: poisoned, the closure panicked
+    state: AtomicU32,
+}
+
+/// Tells the closure given to `call_once_force`
+/// whether an earlier attempt panicked.
+#[derive(Debug)]
+pub struct OnceState {
+    poisoned: bool,
+}
+
+impl OnceState {
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(INCOMPLETE),
+        }
+    }
+
+    /// Runs `f` if no call to `call_once` has completed yet,
+    /// otherwise does nothing. Once this returns, `f` or
+    /// some other closure has run to completion.
+    ///
+    /// Panics if the Once has been poisoned.
+    pub fn call_once(&self, f: impl FnOnce()) {
+        if self.is_completed() {
+            return;
+        }
+        self.call(false, |_| f());
+    }
+
+    /// Like `call_once`, but also runs `f` if
+    /// an earlier closure panicked.
+    pub fn call_once_force(&self, f: impl FnOnce(&OnceState)) {
+        if self.is_completed() {
+            return;
+        }
+        self.call(true, f);
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Acquire) == COMPLETE
+    }
+
+    #[cold]
+    fn call(&self, ignore_poison: bool, f: impl FnOnce(&OnceState)) {
+        let mut state = self.state.load(Acquire);
+        loop {
+            match state {
+                COMPLETE => return,
+                POISONED if !ignore_poison => panic!("Once instance has previously been poisoned"),
+                INCOMPLETE | POISONED => {
+                    if let Err(s) = self
+                        .state
+                        .compare_exchange(state, RUNNING, Acquire, Acquire)
+                    {
+                        state = s;
+                        continue;
+                    }
+                    let mut finish = Finish {
+                        state: &self.state,
+                        completed: false,
+                    };
+                    f(&OnceState {
+                        poisoned: state == POISONED,
+                    });
+                    finish.completed = true;
+                    return;
+                }
+                RUNNING => {
+                    // Let the running thread know it has to wake us up
+                    if let Err(s) =
+                        self.state
+                            .compare_exchange(RUNNING, RUNNING_WAITERS, Acquire, Acquire)
+                    {
+                        state = s;
+                        continue;
+                    }
+                    wait(&self.state, RUNNING_WAITERS);
+                    state = self.state.load(Acquire);
+                }
+                // Running, and the running thread knows we're waiting
+                _ => {
+                    wait(&self.state, RUNNING_WAITERS);
+                    state = self.state.load(Acquire);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Publishes the outcome of the closure, even if it panicked.
+struct Finish<'a> {
+    state: &'a AtomicU32,
+    completed: bool,
+}
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        let new_state = if self.completed { COMPLETE } else { POISONED };
+        if self.state.swap(new_state, Release) == RUNNING_WAITERS {
+            wake_all(self.state);
+        }
+    }
+}
+
+/// A value that's initialized at most once,
+/// by whichever thread gets there first.
+///
+/// If the initializing closure panics, the cell stays
+/// empty and the next caller gets to try again.
+pub struct OnceLock<T> {
+    once: Once,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// The value can be created on one thread and
+// then shared with (and dropped by) another one.
+unsafe impl<T> Sync for OnceLock<T> where T: Send + Sync {}
+unsafe impl<T> Send for OnceLock<T> where T: Send {}
+
+impl<T> OnceLock<T> {
+    pub const fn new() -> Self {
+        Self {
+            once: Once::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if self.once.is_completed() {
+            // Safety: The value was written before the Once completed,
+            // and it's never changed after that.
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Stores `value` if the cell is still empty,
+    /// otherwise hands it back.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// Gets the value, initializing it with `f` first if
+    /// needed. Blocks while another thread is running its `f`.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        self.once.call_once_force(|_| {
+            let value = f();
+            // Safety: Only the thread running the Once gets here,
+            // and nobody reads the value until it's completed.
+            unsafe { (*self.value.get()).write(value) };
+        });
+        self.get().unwrap()
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        if !self.once.is_completed() {
+            return None;
+        }
+        // Marking it as not initialized, so Drop leaves it alone
+        self.once = Once::new();
+        // Safety: The Once was completed, so the value was written
+        Some(unsafe { self.value.get_mut().assume_init_read() })
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceLock<T> {
+    fn drop(&mut self) {
+        if self.once.is_completed() {
+            // Safety: The Once was completed, so the value was written
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// A value that's initialized on first access,
+/// which makes it handy for `static` items.
+///
+/// Just like OnceLock, a panicking initializer lets the next
+/// access try again, which is why `F` has to be `Fn`.
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceLock<T>,
+    init: F,
+}
+
+impl<T, F> Lazy<T, F>
+where
+    F: Fn() -> T,
+{
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceLock::new(),
+            init,
+        }
+    }
+
+    /// Initializes the value if it wasn't already,
+    /// same as dereferencing it.
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(&this.init)
+    }
+}
+
+impl<T, F> Deref for Lazy<T, F>
+where
+    F: Fn() -> T,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::Duration,
+    };
+
+    use super::{Lazy, Once, OnceLock};
+
+    #[test]
+    fn test_once_lock_static() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: OnceLock<String> = OnceLock::new();
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let value = VALUE.get_or_init(|| {
+                        CALLS.fetch_add(1, Relaxed);
+                        // Give the others a chance to block on us
+                        thread::sleep(Duration::from_millis(20));
+                        "hello".to_string()
+                    });
+                    assert_eq!(value, "hello");
+                });
+            }
+        });
+
+        assert_eq!(CALLS.load(Relaxed), 1);
+        assert_eq!(VALUE.set("bye".to_string()), Err("bye".to_string()));
+        assert_eq!(VALUE.get().map(String::as_str), Some("hello"));
+    }
+
+    #[test]
+    fn test_once_lock_retries_after_panic() {
+        let cell = OnceLock::new();
+
+        let result = catch_unwind(AssertUnwindSafe(|| cell.get_or_init(|| panic!("oops"))));
+        assert!(result.is_err());
+        assert!(cell.get().is_none());
+
+        assert_eq!(*cell.get_or_init(|| 5), 5);
+        assert_eq!(cell.into_inner(), Some(5));
+    }
+
+    #[test]
+    fn test_once_poisoning() {
+        let once = Once::new();
+
+        let result = catch_unwind(|| once.call_once(|| panic!("oops")));
+        assert!(result.is_err());
+        assert!(!once.is_completed());
+
+        let result = catch_unwind(|| once.call_once(|| {}));
+        assert!(result.is_err());
+
+        let mut was_poisoned = false;
+        once.call_once_force(|state| was_poisoned = state.is_poisoned());
+        assert!(was_poisoned);
+        assert!(once.is_completed());
+
+        // Done for good now
+        once.call_once(|| unreachable!());
+    }
+
+    #[test]
+    fn test_lazy_static() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static LAZY: Lazy<Vec<u32>> = Lazy::new(|| {
+            CALLS.fetch_add(1, Relaxed);
+            vec![1, 2, 3]
+        });
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| assert_eq!(LAZY.len(), 3));
+            }
+        });
+
+        assert_eq!(*LAZY, [1, 2, 3]);
+        assert_eq!(CALLS.load(Relaxed), 1);
+    }
+}
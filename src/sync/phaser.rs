@@ -0,0 +1,250 @@
+use crate::locks::{condvar::Condvar, mutex::Mutex};
+
+type OnAdvance = Box<dyn FnMut(u64, usize) -> bool + Send>;
+
+/// A reusable barrier for a changing number of parties,
+/// like Java's Phaser.
+///
+/// Parties can `register` and `arrive_and_deregister` at any
+/// time. Once every registered party has arrived, the phaser
+/// advances to the next phase, numbered from 0, and lets
+/// everybody waiting go.
+///
+/// Right before advancing, the `on_advance` callback gets the
+/// phase that's ending and the number of registered parties,
+/// and terminates the phaser by returning true. Without a callback,
+/// the phaser terminates once no parties are registered anymore.
+/// Methods returning a phase number return None once it has terminated.
+pub struct Phaser {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+struct State {
+    parties: usize,
+    arrived: usize,
+    phase: u64,
+    terminated: bool,
+    on_advance: Option<OnAdvance>,
+}
+
+impl Phaser {
+    pub const fn new(parties: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                parties,
+                arrived: 0,
+                phase: 0,
+                terminated: false,
+                on_advance: None,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// The callback runs while the phaser is locked,
+    /// so it can't call any of the phaser's methods.
+    pub fn with_on_advance(
+        parties: usize,
+        on_advance: impl FnMut(u64, usize) -> bool + Send + 'static,
+    ) -> Self {
+        let phaser = Self::new(parties);
+        phaser.state.lock().on_advance = Some(Box::new(on_advance));
+        phaser
+    }
+
+    /// Adds a party, returning the phase it joins in.
+    pub fn register(&self) -> Option<u64> {
+        let mut state = self.state.lock();
+        if state.terminated {
+            return None;
+        }
+        state.parties += 1;
+        Some(state.phase)
+    }
+
+    /// Arrives without waiting for the others,
+    /// returning the phase that was arrived at.
+    ///
+    /// Panics if more parties arrive than are registered.
+    pub fn arrive(&self) -> Option<u64> {
+        self.arrive_inner(false)
+    }
+
+    /// Arrives and leaves the phaser for good.
+    pub fn arrive_and_deregister(&self) -> Option<u64> {
+        self.arrive_inner(true)
+    }
+
+    /// Arrives and waits until all the others did too,
+    /// returning the phase that was arrived at.
+    pub fn arrive_and_wait(&self) -> Option<u64> {
+        let mut state = self.state.lock();
+        let phase = self.arrive_locked(&mut state, false)?;
+        let _state = self.condvar.wait_while(state, |s| s.phase == phase);
+        Some(phase)
+    }
+
+    /// The current phase.
+    pub fn phase(&self) -> Option<u64> {
+        let state = self.state.lock();
+        (!state.terminated).then_some(state.phase)
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.state.lock().terminated
+    }
+
+    pub fn registered_parties(&self) -> usize {
+        self.state.lock().parties
+    }
+
+    /// Parties that arrived in the current phase.
+    pub fn arrived_parties(&self) -> usize {
+        self.state.lock().arrived
+    }
+
+    fn arrive_inner(&self, deregister: bool) -> Option<u64> {
+        self.arrive_locked(&mut self.state.lock(), deregister)
+    }
+
+    fn arrive_locked(&self, state: &mut State, deregister: bool) -> Option<u64> {
+        if state.terminated {
+            return None;
+        }
+        assert!(
+            state.arrived < state.parties,
+            "more arrivals than registered parties in phaser"
+        );
+        if deregister {
+            state.parties -= 1;
+        } else {
+            state.arrived += 1;
+        }
+
+        let phase = state.phase;
+        if state.arrived == state.parties {
+            let parties = state.parties;
+            let mut on_advance = state.on_advance.take();
+            let mut advance = Advance {
+                state,
+                condvar: &self.condvar,
+                // Until the callback returned, assume it panicked
+                terminated: true,
+            };
+            advance.terminated = match &mut on_advance {
+                Some(on_advance) => on_advance(phase, parties),
+                None => parties == 0,
+            };
+            advance.state.on_advance = on_advance;
+        }
+        Some(phase)
+    }
+}
+
+/// Advances to the next phase and lets the waiting threads go
+/// when dropped, even if the `on_advance` callback panicked,
+/// which terminates the phaser instead of leaving them stuck.
+struct Advance<'a> {
+    state: &'a mut State,
+    condvar: &'a Condvar,
+    terminated: bool,
+}
+
+impl Drop for Advance<'_> {
+    fn drop(&mut self) {
+        self.state.terminated = self.terminated;
+        self.state.arrived = 0;
+        self.state.phase += 1;
+        self.condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+    };
+
+    use super::Phaser;
+
+    #[test]
+    fn test_phaser_dynamic_parties() {
+        let phaser = Phaser::new(1);
+        let work = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            // Worker i joins in phase 0 and leaves after phase i
+            for i in 0..3 {
+                assert_eq!(phaser.register(), Some(0));
+                let (phaser, work) = (&phaser, &work);
+                s.spawn(move || {
+                    for phase in 0..i {
+                        work.fetch_add(1, Relaxed);
+                        assert_eq!(phaser.arrive_and_wait(), Some(phase));
+                    }
+                    work.fetch_add(1, Relaxed);
+                    phaser.arrive_and_deregister();
+                });
+            }
+
+            // All three workers did their part in phase 0, two in phase 1.
+            // Some might already be working on the next phase.
+            assert_eq!(phaser.arrive_and_wait(), Some(0));
+            assert!(work.load(Relaxed) >= 3);
+            assert_eq!(phaser.arrive_and_wait(), Some(1));
+            assert!(work.load(Relaxed) >= 5);
+            assert_eq!(phaser.arrive_and_wait(), Some(2));
+            assert_eq!(work.load(Relaxed), 6);
+        });
+
+        assert_eq!(phaser.registered_parties(), 1);
+        assert_eq!(phaser.phase(), Some(3));
+
+        // The last party leaving terminates it
+        assert_eq!(phaser.arrive_and_deregister(), Some(3));
+        assert!(phaser.is_terminated());
+        assert_eq!(phaser.register(), None);
+    }
+
+    #[test]
+    fn test_phaser_on_advance() {
+        let phaser = Phaser::with_on_advance(2, |phase, parties| {
+            assert_eq!(parties, 2);
+            phase == 2
+        });
+
+        thread::scope(|s| {
+            s.spawn(|| while phaser.arrive_and_wait().is_some() {});
+            let mut phases = Vec::new();
+            while let Some(phase) = phaser.arrive_and_wait() {
+                phases.push(phase);
+            }
+            assert_eq!(phases, [0, 1, 2]);
+        });
+
+        assert!(phaser.is_terminated());
+        assert_eq!(phaser.phase(), None);
+    }
+
+    #[test]
+    fn test_phaser_on_advance_panic() {
+        let phaser = Phaser::with_on_advance(2, |_, _| panic!("oops"));
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| phaser.arrive_and_wait());
+            while phaser.arrived_parties() == 0 {
+                thread::yield_now();
+            }
+            let result = catch_unwind(AssertUnwindSafe(|| phaser.arrive()));
+            assert!(result.is_err());
+            // The waiter still gets to leave phase 0
+            assert_eq!(waiter.join().unwrap(), Some(0));
+        });
+
+        assert!(phaser.is_terminated());
+        assert_eq!(phaser.arrive_and_wait(), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering::*},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use super::latch::wait_until_zero;
+use crate::atomic_wait::wake_all;
+
+/// Go style wait group: every worker gets a clone of the
+/// group, and `wait` returns once all clones are dropped.
+pub struct WaitGroup {
+    /// Number of handles still around.
+    count: Arc<AtomicU32>,
+}
+
+impl WaitGroup {
+    pub fn new() -> Self {
+        Self {
+            count: Arc::new(AtomicU32::new(1)),
+        }
+    }
+
+    /// Drops this handle and waits for all others to be dropped.
+    pub fn wait(self) {
+        let count = self.count.clone();
+        drop(self);
+        wait_until_zero(&count, None);
+    }
+
+    /// Like `wait`, but returns false if some handles
+    /// are still alive after `timeout`.
+    pub fn wait_timeout(self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let count = self.count.clone();
+        drop(self);
+        wait_until_zero(&count, Some(deadline))
+    }
+}
+
+impl Default for WaitGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for WaitGroup {
+    fn clone(&self) -> Self {
+        // Can't overflow in practice: every handle takes up memory
+        self.count.fetch_add(1, Relaxed);
+        Self {
+            count: self.count.clone(),
+        }
+    }
+}
+
+impl Drop for WaitGroup {
+    fn drop(&mut self) {
+        if self.count.fetch_sub(1, Release) == 1 {
+            wake_all(&self.count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::Duration,
+    };
+
+    use super::WaitGroup;
+
+    #[test]
+    fn test_wait_group() {
+        static DONE: AtomicUsize = AtomicUsize::new(0);
+        let wg = WaitGroup::new();
+
+        for _ in 0..4 {
+            let wg = wg.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                DONE.fetch_add(1, Relaxed);
+                drop(wg);
+            });
+        }
+
+        wg.wait();
+        assert_eq!(DONE.load(Relaxed), 4);
+    }
+
+    #[test]
+    fn test_wait_group_timeout() {
+        let wg = WaitGroup::new();
+        let worker = wg.clone();
+        assert!(!wg.clone().wait_timeout(Duration::from_millis(10)));
+        drop(worker);
+        assert!(wg.wait_timeout(Duration::from_millis(10)));
+    }
+}